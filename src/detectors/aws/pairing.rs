@@ -0,0 +1,342 @@
+use crate::detectors::aws::might_contain_key;
+use crate::scanners::{find_all, SearchMatch};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::path::Path;
+
+// Keys in the same file further apart than this many lines are not paired.
+const MAX_PAIR_DISTANCE: usize = 100;
+// Keys in different files of the same package are only paired if both are assigned to names that
+// suggest what they are, and are only tried after every pair in the same file.
+const DIFFERENT_FILE_PENALTY: i64 = 200;
+// Only this much of the text before a key on its line is kept to look for a name in, as minified
+// files may have megabytes on a single line.
+const MAX_NAME_LEN: usize = 100;
+// Added for each key that is assigned to a name that suggests what it is.
+const NAME_HINT_BONUS: i64 = 20;
+
+lazy_static! {
+    // Keys must be quoted, else the false positive rate is too large.
+    static ref ACCESS_KEY_REGEX: Regex =
+        Regex::new("('|\")(?:ASIA|AKIA|AROA|AIDA)([A-Z0-7]{16})('|\")").unwrap();
+    static ref SECRET_KEY_REGEX: Regex = Regex::new("('|\")([a-zA-Z0-9+/]{40})('|\")").unwrap();
+    // Session tokens are long base64 strings. Those issued by STS start with one of these, which
+    // encode the start of the same binary header, so they don't need to be quoted.
+    static ref SESSION_TOKEN_REGEX: Regex =
+        Regex::new("(?:IQoJ|IQoD|FwoG|FQoG|AgoJ)[A-Za-z0-9+/]{96,}={0,2}").unwrap();
+    // When a session token is saved, its expiry is often saved next to it, such as `Expiration`
+    // in the output of `aws sts get-session-token` or `aws_session_expiration` in a credentials
+    // file.
+    static ref EXPIRATION_REGEX: Regex = Regex::new(
+        r#"(?i)expir[a-z_]*["']?\s*[:=]\s*["']?(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2})?)"#
+    )
+    .unwrap();
+}
+
+// A session token is only paired with an expiry saved within this many lines of it.
+const MAX_EXPIRATION_DISTANCE: usize = 4;
+
+/// A value in a file that looks like an access key ID or a secret access key.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub value: String,
+    pub search_match: SearchMatch,
+    // Whether the value is assigned to a name that suggests what it is, such as
+    // `aws_secret_access_key = `.
+    pub named: bool,
+}
+
+/// Everything in a file that may be part of a set of credentials.
+#[derive(Debug, Default)]
+pub struct Candidates {
+    pub access_keys: Vec<Candidate>,
+    pub secret_keys: Vec<Candidate>,
+    pub session_tokens: Vec<Candidate>,
+    pub expirations: Vec<(SearchMatch, DateTime<Utc>)>,
+}
+
+impl Candidates {
+    pub fn extend(&mut self, other: Candidates) {
+        self.access_keys.extend(other.access_keys);
+        self.secret_keys.extend(other.secret_keys);
+        self.session_tokens.extend(other.session_tokens);
+        self.expirations.extend(other.expirations);
+    }
+
+    /// Finds the session token that most likely belongs to a temporary access key, preferring
+    /// the closest one in the same file, along with its expiry if that was saved next to it.
+    pub fn session_token_for(
+        &self,
+        access_key: &SearchMatch,
+    ) -> Option<(&Candidate, Option<DateTime<Utc>>)> {
+        let token = self.session_tokens.iter().min_by_key(|token| {
+            let token = &token.search_match;
+            match token.path == access_key.path {
+                true => token.line_number.abs_diff(access_key.line_number),
+                false => usize::MAX,
+            }
+        })?;
+        let expiration = self
+            .expirations
+            .iter()
+            .filter(|(m, _)| m.path == token.search_match.path)
+            .map(|(m, expiration)| {
+                let distance = m.line_number.abs_diff(token.search_match.line_number);
+                (distance, *expiration)
+            })
+            .filter(|(distance, _)| *distance <= MAX_EXPIRATION_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, expiration)| expiration);
+        Some((token, expiration))
+    }
+}
+
+/// An access key and a secret key that may belong together, with how likely that is.
+#[derive(Debug)]
+pub struct KeyPair {
+    // The match of the access key.
+    pub search_match: SearchMatch,
+    pub access_key: String,
+    pub secret_key: String,
+    pub score: i64,
+}
+
+/// Returns the possible credentials in a file. Secret keys and session tokens are returned for
+/// every file, as they may be paired with an access key in another file of the package.
+pub fn find_candidates(path: &Path, contents: &[u8]) -> Candidates {
+    let to_candidates = |regex: &Regex, quoted: bool, is_name: fn(&str) -> bool| {
+        find_all(path, contents, regex)
+            .into_iter()
+            .map(|search_match| Candidate {
+                value: match quoted {
+                    true => search_match.text[1..search_match.text.len() - 1].to_string(),
+                    false => search_match.text.clone(),
+                },
+                named: is_name(&name_before(contents, &search_match)),
+                search_match,
+            })
+            .collect::<Vec<_>>()
+    };
+    let access_keys = match might_contain_key(contents) {
+        true => to_candidates(&ACCESS_KEY_REGEX, true, is_access_key_name),
+        false => vec![],
+    };
+    let secret_keys = to_candidates(&SECRET_KEY_REGEX, true, is_secret_key_name)
+        .into_iter()
+        .filter(|c| !is_hash(&c.value))
+        .collect();
+    let session_tokens = to_candidates(&SESSION_TOKEN_REGEX, false, |_| false);
+    let expirations = match session_tokens.is_empty() {
+        true => vec![],
+        false => find_all(path, contents, &EXPIRATION_REGEX)
+            .into_iter()
+            .filter_map(|search_match| {
+                let captures = EXPIRATION_REGEX.captures(search_match.text.as_bytes())?;
+                let expiration = parse_expiration(&String::from_utf8_lossy(&captures[1]))?;
+                Some((search_match, expiration))
+            })
+            .collect(),
+    };
+    Candidates {
+        access_keys,
+        secret_keys,
+        session_tokens,
+        expirations,
+    }
+}
+
+// The text before a match on its line, such as `aws_secret_access_key = `, up to `MAX_NAME_LEN`
+// bytes of it.
+fn name_before(contents: &[u8], search_match: &SearchMatch) -> String {
+    let start = search_match.offset;
+    let line_start = start - (search_match.column - 1);
+    let name_start = line_start.max(start.saturating_sub(MAX_NAME_LEN));
+    String::from_utf8_lossy(&contents[name_start..start]).to_string()
+}
+
+/// Pairs each access key with the best scoring secret keys, at most `limit` different ones per
+/// access key. Secret keys are only paired if they are in the same file within
+/// `MAX_PAIR_DISTANCE` lines, or if both keys are assigned to names that suggest what they are.
+pub fn pair(access_keys: &[Candidate], secret_keys: &[Candidate], limit: usize) -> Vec<KeyPair> {
+    let mut pairs = vec![];
+    for access_key in access_keys {
+        let access = &access_key.search_match;
+        let best = secret_keys
+            .iter()
+            .filter_map(|secret_key| {
+                let secret = &secret_key.search_match;
+                let line_distance = (access.path == secret.path)
+                    .then(|| access.line_number.abs_diff(secret.line_number));
+                let pairable = match line_distance {
+                    Some(distance) => distance <= MAX_PAIR_DISTANCE,
+                    None => access_key.named && secret_key.named,
+                };
+                pairable.then(|| {
+                    let score = score(line_distance, access_key.named, secret_key.named);
+                    (score, secret_key)
+                })
+            })
+            .sorted_by_key(|(score, _)| -score)
+            .unique_by(|(_, secret_key)| &secret_key.value)
+            .take(limit);
+        for (score, secret_key) in best {
+            pairs.push(KeyPair {
+                search_match: access_key.search_match.clone(),
+                access_key: access_key.value.clone(),
+                secret_key: secret_key.value.clone(),
+                score,
+            });
+        }
+    }
+    pairs
+}
+
+/// Scores how likely an access key and secret key are to belong together. Keys in the same file
+/// score higher the closer together they are, and each key scores higher if the name it is
+/// assigned to suggests what it is.
+pub fn score(line_distance: Option<usize>, access_key_named: bool, secret_key_named: bool) -> i64 {
+    let mut score = match line_distance {
+        Some(distance) => -(distance.min(MAX_PAIR_DISTANCE) as i64),
+        None => -DIFFERENT_FILE_PENALTY,
+    };
+    if access_key_named {
+        score += NAME_HINT_BONUS;
+    }
+    if secret_key_named {
+        score += NAME_HINT_BONUS;
+    }
+    score
+}
+
+pub fn is_access_key_name(name: &str) -> bool {
+    let name = normalize_name(name);
+    name.contains("accesskey") || name.contains("keyid")
+}
+
+pub fn is_secret_key_name(name: &str) -> bool {
+    normalize_name(name).contains("secret")
+}
+
+// `aws_access_key_id`, `AWS_ACCESS_KEY_ID` and `awsAccessKeyId` all become `awsaccesskeyid`.
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// Quoted SHA-1 hashes, such as git commits in lock files, also match the secret key pattern but
+// are only lowercase hex. A random secret key would almost never be.
+fn is_hash(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+// Expiry times are usually RFC 3339, but may leave out the timezone, in which case they are UTC.
+fn parse_expiration(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.replacen(' ', "T", 1);
+    DateTime::parse_from_rfc3339(&text)
+        .map(|d| d.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|d| DateTime::<Utc>::from_utc(d, Utc))
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(value: &str, path: &str, line_number: usize, named: bool) -> Candidate {
+        Candidate {
+            value: value.to_string(),
+            search_match: SearchMatch {
+                text: format!("\"{value}\""),
+                offset: 0,
+                line_number,
+                column: 1,
+                path: path.into(),
+            },
+            named,
+        }
+    }
+
+    fn secrets(pairs: &[KeyPair]) -> Vec<&str> {
+        pairs.iter().map(|p| p.secret_key.as_str()).collect()
+    }
+
+    #[test]
+    fn score_ranks_closer_and_named_keys_higher() {
+        assert!(score(Some(1), false, false) > score(Some(2), false, false));
+        assert!(score(Some(10), true, true) > score(Some(10), true, false));
+        assert!(score(Some(10), true, false) > score(Some(10), false, false));
+        // Every pair in the same file scores higher than one across files.
+        assert!(score(Some(MAX_PAIR_DISTANCE), false, false) > score(None, true, true));
+        assert_eq!(
+            score(Some(MAX_PAIR_DISTANCE), false, false),
+            score(Some(MAX_PAIR_DISTANCE * 2), false, false)
+        );
+    }
+
+    #[test]
+    fn pair_prefers_the_closest_secret_key_in_the_same_file() {
+        let access_keys = [candidate("AKIA", "a.py", 10, false)];
+        let secret_keys = [
+            candidate("far", "a.py", 30, false),
+            candidate("near", "a.py", 11, false),
+            candidate("named", "a.py", 35, true),
+            candidate("too-far", "a.py", 10 + MAX_PAIR_DISTANCE + 1, false),
+        ];
+        let pairs = pair(&access_keys, &secret_keys, 10);
+        assert_eq!(secrets(&pairs), ["near", "named", "far"]);
+        assert_eq!(pairs[0].score, -1);
+        assert_eq!(pairs[0].access_key, "AKIA");
+    }
+
+    #[test]
+    fn pair_only_pairs_named_keys_across_files() {
+        let secret_keys = [
+            candidate("unnamed", "b.py", 1, false),
+            candidate("named", "b.py", 2, true),
+        ];
+
+        let unnamed_access_key = [candidate("AKIA", "a.py", 1, false)];
+        assert!(pair(&unnamed_access_key, &secret_keys, 10).is_empty());
+
+        let named_access_key = [candidate("AKIA", "a.py", 1, true)];
+        let pairs = pair(&named_access_key, &secret_keys, 10);
+        assert_eq!(secrets(&pairs), ["named"]);
+        assert_eq!(
+            pairs[0].score,
+            -DIFFERENT_FILE_PENALTY + 2 * NAME_HINT_BONUS
+        );
+    }
+
+    #[test]
+    fn pair_limits_unique_secret_keys_per_access_key() {
+        let access_keys = [
+            candidate("AKIA1", "a.py", 1, false),
+            candidate("AKIA2", "a.py", 50, false),
+        ];
+        let secret_keys = [
+            candidate("first", "a.py", 2, false),
+            candidate("first", "a.py", 3, false),
+            candidate("second", "a.py", 4, false),
+            candidate("third", "a.py", 49, false),
+        ];
+        let pairs = pair(&access_keys, &secret_keys, 2);
+        let by_access_key = |access_key| {
+            pairs
+                .iter()
+                .filter(|p| p.access_key == access_key)
+                .map(|p| (p.secret_key.as_str(), p.score))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(by_access_key("AKIA1"), [("first", -1), ("second", -3)]);
+        assert_eq!(by_access_key("AKIA2"), [("third", -1), ("second", -46)]);
+    }
+}
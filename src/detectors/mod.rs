@@ -0,0 +1,132 @@
+mod aws;
+
+use crate::scanners::{DownloadedPackage, QuickCheck, ScannerMatch};
+use crate::sources::SourceType;
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref DETECTORS: Vec<Box<dyn Detector>> = vec![Box::new(aws::Aws)];
+}
+
+/// A type of credential to look for in packages.
+pub trait Detector: Send + Sync {
+    /// A short unique name, which is recorded in each match the detector finds.
+    fn id(&self) -> &'static str;
+
+    /// What to look for when deciding whether a package needs the full check.
+    fn quick_check(&self) -> &QuickCheck;
+
+    /// Creates an extractor for the full check of a single package.
+    fn extractor(&self) -> Box<dyn Extractor>;
+
+    /// Returns the matches with credentials that work, with anything learned about them added to
+    /// their details. Only live credentials are reported, so by default nothing found by a
+    /// detector that can't check them is.
+    fn verify(&self, matches: Vec<ScannerMatch>) -> Result<Vec<LiveKey>> {
+        println!(
+            "Found {} {} keys, which can't be verified",
+            matches.len(),
+            self.id()
+        );
+        Ok(vec![])
+    }
+
+    /// The account that owns the credential in a match, if that can be told without verifying it.
+    fn account_id(&self, _scanner_match: &ScannerMatch) -> Option<String> {
+        None
+    }
+
+    /// The markdown describing a single key in a report, which is rendered with its `Finding`. The
+    /// details of the match are available as `details`.
+    fn report_template(&self) -> &'static str;
+}
+
+/// Collects credentials from each file in a package. Parts of a credential may be in different
+/// files, so they are only put together into matches once every file has been visited.
+pub trait Extractor {
+    fn visit(&mut self, path: &Path, contents: &[u8]);
+
+    fn finish(self: Box<Self>, package: &DownloadedPackage) -> Vec<ScannerMatch>;
+}
+
+pub fn all() -> &'static [Box<dyn Detector>] {
+    &DETECTORS
+}
+
+/// The account that owns the credential in a match, according to the detector that found it.
+pub fn account_id(scanner_match: &ScannerMatch) -> Option<String> {
+    all()
+        .iter()
+        .find(|d| d.id() == scanner_match.detector)?
+        .account_id(scanner_match)
+}
+
+/// Checks each match with the detector that found it.
+pub fn verify(matches: Vec<ScannerMatch>) -> Result<Vec<LiveKey>> {
+    let mut matches = matches.into_iter().into_group_map_by(|m| m.detector);
+    let mut live_keys = vec![];
+    for detector in all() {
+        let Some(matches) = matches.remove(detector.id()) else {
+            continue;
+        };
+        let verified = detector
+            .verify(matches)
+            .with_context(|| format!("Error checking {} keys", detector.id()))?;
+        live_keys.extend(verified);
+    }
+    Ok(live_keys)
+}
+
+/// A match whose credentials were verified to work.
+#[derive(Debug, Clone)]
+pub struct LiveKey {
+    pub scanner_match: ScannerMatch,
+}
+
+impl Eq for LiveKey {}
+
+impl PartialEq for LiveKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.scanner_match == other.scanner_match
+    }
+}
+
+impl LiveKey {
+    pub fn ordering_tuple(
+        &self,
+    ) -> (
+        &SourceType,
+        &String,
+        &String,
+        &str,
+        &String,
+        &PathBuf,
+        &usize,
+    ) {
+        (
+            &self.scanner_match.downloaded_package.package.source,
+            &self.scanner_match.downloaded_package.package.name,
+            &self.scanner_match.downloaded_package.package.version,
+            self.scanner_match.detector,
+            &self.scanner_match.key,
+            &self.scanner_match.search_match.path,
+            &self.scanner_match.search_match.line_number,
+        )
+    }
+}
+
+impl Ord for LiveKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ordering_tuple().cmp(&other.ordering_tuple())
+    }
+}
+
+impl PartialOrd for LiveKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
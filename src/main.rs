@@ -1,19 +1,21 @@
-mod aws;
+mod detectors;
 mod reporter;
 mod scanners;
 mod sources;
 mod state;
 
-use crate::aws::check_aws_keys;
-use crate::scanners::Scanner;
-use crate::sources::SourceType;
+use crate::detectors::LiveKey;
+use crate::scanners::{PossiblyMatchedPackage, Scanner, ScannerConfig, ScannerMatch, SkipReason};
+use crate::sources::{PackageToProcess, SourceType, Unsupported};
 use crate::state::State;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
 use clap::{Parser, ValueEnum};
 
 use crate::reporter::create_findings;
 use itertools::Itertools;
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -40,11 +42,37 @@ enum Action {
             required = true
         )]
         sources: Vec<SourceType>,
+        /// Scan packages published since this time instead of those after the saved cursors. Either
+        /// an RFC 3339 timestamp or a time relative to now, such as `6h` or `2d`
+        #[clap(long, value_parser = parse_time, conflicts_with = "save")]
+        since: Option<DateTime<Utc>>,
+        /// The end of the `--since` window, defaulting to now
+        #[clap(long, value_parser = parse_time, requires = "since")]
+        until: Option<DateTime<Utc>>,
+        /// Advance the backfill cursors, which walk each source from the beginning, instead of the
+        /// live cursors
+        #[clap(long, conflicts_with = "since")]
+        backfill: bool,
+        #[command(flatten)]
+        scanner: ScannerConfig,
     },
     SetupState {
         #[clap(default_value = "state.json")]
         path: PathBuf,
     },
+    /// Scan a specific package, without reading or advancing any cursors
+    ScanPackage {
+        #[arg(value_enum)]
+        source: SourceType,
+        name: String,
+        version: Option<String>,
+        file_name: Option<String>,
+        /// Used to read source configuration, such as the URL of a simple index
+        #[clap(long, default_value = "state.json")]
+        state: PathBuf,
+        #[command(flatten)]
+        scanner: ScannerConfig,
+    },
 }
 
 fn main() -> Result<()> {
@@ -55,7 +83,14 @@ fn main() -> Result<()> {
             save,
             limit,
             sources,
-        } => run(state, save, limit, sources),
+            since,
+            until,
+            backfill,
+            scanner,
+        } => {
+            let window = since.map(|since| (since, until.unwrap_or_else(Utc::now)));
+            run(state, save, limit, sources, window, backfill, scanner)
+        }
         Action::SetupState { path } => {
             let mut state = State::load(&path)?;
             for source_type in SourceType::value_variants() {
@@ -69,82 +104,189 @@ fn main() -> Result<()> {
             state.save(&path)?;
             Ok(())
         }
+        Action::ScanPackage {
+            source,
+            name,
+            version,
+            file_name,
+            state,
+            scanner,
+        } => scan_package(state, source, name, version, file_name, scanner),
+    }
+}
+
+fn scan_package(
+    state_path: PathBuf,
+    source_type: SourceType,
+    name: String,
+    version: Option<String>,
+    file_name: Option<String>,
+    scanner_config: ScannerConfig,
+) -> Result<()> {
+    let state = State::load(&state_path)?;
+    let source = source_type.create_source(state.data_for_source(&source_type))?;
+    let packages: Vec<_> = source
+        .get_package(&name, version.as_deref())
+        .with_context(|| format!("Failed to get package {name} from source {source_type:?}"))?
+        .into_iter()
+        .filter(|p| file_name.as_ref().is_none_or(|f| p.file_name() == f))
+        .collect();
+    if packages.is_empty() {
+        bail!("No files found for {source_type:?} / {name}");
     }
+    println!(
+        "Found {} files for {source_type:?} / {name}",
+        packages.len()
+    );
+
+    let (live_keys, _, failures) = scan_packages(packages, scanner_config)?;
+    create_findings(live_keys)?;
+    if !failures.is_empty() {
+        bail!("Failed to scan {} files", failures.len());
+    }
+    Ok(())
 }
 
-fn run(state_path: PathBuf, save: bool, limit: usize, sources: Vec<SourceType>) -> Result<()> {
+// Parses an RFC 3339 timestamp, or a duration before now such as `30m`, `6h`, `2d` or `1w`.
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let invalid = || format!("{value} is not an RFC 3339 timestamp or a relative time like 6h");
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => return Err(invalid()),
+    };
+    Ok(Utc::now() - duration)
+}
+
+fn run(
+    state_path: PathBuf,
+    save: bool,
+    limit: usize,
+    sources: Vec<SourceType>,
+    window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    backfill: bool,
+    scanner_config: ScannerConfig,
+) -> Result<()> {
     let mut state = State::load(&state_path)?;
     let sources: Vec<_> = sources.into_iter().unique().collect();
 
+    // Windows are a one-off rescan, so they leave the retry queue alone.
+    let retries: Vec<_> = match window {
+        Some(_) => vec![],
+        None => sources
+            .iter()
+            .flat_map(|s| state.take_due_retries(s))
+            .collect(),
+    };
+
     let query_results: Result<Vec<_>> = sources
         .into_par_iter()
-        .map(|s| -> Result<_> {
+        .map(|s| -> Result<Option<_>> {
             let source_data = state.data_for_source(&s);
             let mut source = s.create_source(source_data).expect("Error creating source");
-            println!("Fetching data for source {source}");
-            let packages = source
-                .get_new_packages_to_process(limit)
+            let result = match window {
+                Some((since, until)) => {
+                    println!("Fetching data for source {s:?} between {since} and {until}");
+                    source.get_packages_in_window(since, until, limit)
+                }
+                None if backfill => {
+                    println!("Backfilling source {source}");
+                    source.get_backfill_packages_to_process(limit)
+                }
+                None => {
+                    println!("Fetching data for source {source}");
+                    source.get_new_packages_to_process(limit)
+                }
+            };
+            // Other sources may support it, so this one is left out rather than failing the run.
+            if let Some(unsupported) = result
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<Unsupported>())
+            {
+                eprintln!("Skipping source {s:?}: {unsupported}");
+                return Ok(None);
+            }
+            let packages = result
                 .with_context(|| format!("Failed to get packages to process for source {s:?}"))?;
             println!("Source {:?} found {} packages", s, packages.len());
             let stats = source.get_stats();
             stats.add_packages_searched(packages.len() as u64);
-            Ok((s, source, packages))
+            Ok(Some((s, source, packages)))
         })
         .collect();
     let query_results = query_results?;
-    let (source_data, packages): (Vec<_>, Vec<_>) = query_results
+    let (mut source_data, packages): (Vec<_>, Vec<_>) = query_results
         .into_iter()
+        .flatten()
         .map(|(t, d, p)| ((t, d), p))
         .unzip();
-    let flat_packages: Vec<_> = packages.into_iter().flatten().collect();
-
-    let scanner = Scanner {};
 
-    let all_matches: Result<Vec<_>> = flat_packages
-        .into_par_iter()
-        .flat_map(|package| -> Result<_> {
-            let download = scanner.download_package(&package).with_context(|| {
-                format!(
-                    "Failed to download package {:?} / {} @ {}",
-                    package.source, package.name, package.version
-                )
-            })?;
-            let name = download.package.name.clone();
-            let version = download.package.version.clone();
-            let source = download.package.source.clone();
-            let result = scanner.quick_check(download).with_context(|| {
-                format!(
-                    "Error running quick check on {source:?} / {name} @ {version}"
-                )
-            });
-            println!(
-                "Finished quick check on {source:?} / {name} @ {version}"
-            );
-            result
-        })
+    if !retries.is_empty() {
+        println!("Retrying {} previously failed packages", retries.len());
+    }
+    let previous_attempts: HashMap<_, _> = retries
+        .iter()
+        .map(|f| (f.package.download_url.clone(), f.attempts))
+        .collect();
+    let flat_packages: Vec<_> = packages
+        .into_iter()
         .flatten()
-        .map(|matched| {
-            println!(
-                "running full check on {:?} / {} @ {}\n - Previous match:\n{}",
-                matched.downloaded_package.package.source,
-                matched.downloaded_package.package.name,
-                matched.downloaded_package.package.version,
-                matched
-                    .matches
-                    .iter()
-                    .map(|v| { v.lines.chars().take(250).join("") })
-                    .join("\n\n")
-            );
-            scanner.full_check(matched)
-        })
+        .chain(retries.into_iter().map(|f| f.package))
+        .unique_by(|p| p.download_url.clone())
         .collect();
 
-    let live_keys = check_aws_keys(all_matches?.into_iter().flatten().collect())
-        .context("Error checking AWS keys")?;
-    println!("Live keys: {live_keys:?}");
+    let (live_keys, unverified_keys, failures) = scan_packages(flat_packages, scanner_config)?;
 
     create_findings(live_keys)?;
 
+    for (source_type, account_id) in unverified_keys {
+        if let Some((_, source)) = source_data.iter_mut().find(|(t, _)| *t == source_type) {
+            source.get_stats().add_unverified_key(&account_id);
+        }
+    }
+
+    for (package, error) in failures {
+        // Skipped packages would be skipped again, so they are counted instead of retried.
+        if let Some(reason) = error.downcast_ref::<SkipReason>() {
+            eprintln!(
+                "Skipped {:?} / {} @ {}: {reason}",
+                package.source, package.name, package.version
+            );
+            if let Some((_, source)) = source_data.iter_mut().find(|(t, _)| *t == package.source) {
+                source.get_stats().add_package_skipped(reason.kind());
+            }
+            continue;
+        }
+        let attempts = previous_attempts
+            .get(&package.download_url)
+            .copied()
+            .unwrap_or(0);
+        let failed = state.record_failure(package, attempts, &error);
+        if failed.is_exhausted() {
+            eprintln!(
+                "Giving up on {:?} / {} @ {} after {} attempts, marking it as unscanned",
+                failed.package.source, failed.package.name, failed.package.version, failed.attempts
+            );
+            if let Some((_, source)) = source_data
+                .iter_mut()
+                .find(|(t, _)| *t == failed.package.source)
+            {
+                source.get_stats().add_packages_unscanned(1);
+            }
+        }
+    }
+
     if save {
         for (source_type, source_data) in source_data {
             state.update_state(source_type, source_data.to_state()?);
@@ -154,3 +296,124 @@ fn run(state_path: PathBuf, save: bool, limit: usize, sources: Vec<SourceType>)
 
     Ok(())
 }
+
+// A package that failed to download or scan, and why.
+type ScanFailure = (PackageToProcess, anyhow::Error);
+
+// A key that was found but didn't work, as the source it was found in and the account that owns it.
+type UnverifiedKey = (SourceType, String);
+
+// Scans packages, returning any live keys, the owners of keys that didn't work, and the packages
+// that failed to download or scan.
+fn scan_packages(
+    packages: Vec<PackageToProcess>,
+    scanner_config: ScannerConfig,
+) -> Result<(Vec<LiveKey>, Vec<UnverifiedKey>, Vec<ScanFailure>)> {
+    let scanner = Scanner::new(scanner_config);
+
+    let results: Vec<_> = packages
+        .into_par_iter()
+        .map(|package| {
+            let result = scan_file(&scanner, &package);
+            (package, result)
+        })
+        .collect();
+
+    let mut all_matches = vec![];
+    let mut failures = vec![];
+    for (package, result) in results {
+        match result {
+            Ok(matches) => all_matches.extend(matches),
+            Err(e) => {
+                eprintln!("{e:?}");
+                failures.push((package, e));
+            }
+        }
+    }
+
+    // A key may have been paired several times, so each key in a package is counted once.
+    let owned_keys: Vec<_> = all_matches
+        .iter()
+        .filter_map(|m| {
+            let account_id = detectors::account_id(m)?;
+            Some((
+                m.downloaded_package.package.clone(),
+                m.key.clone(),
+                account_id,
+            ))
+        })
+        .unique_by(|(package, key, _)| (package.download_url.clone(), key.clone()))
+        .collect();
+
+    let live_keys = detectors::verify(all_matches)?;
+    println!("Live keys: {live_keys:?}");
+
+    let unverified_keys = owned_keys
+        .into_iter()
+        .filter(|(package, key, _)| {
+            !live_keys.iter().any(|live| {
+                live.scanner_match.downloaded_package.package.download_url == package.download_url
+                    && live.scanner_match.key == *key
+            })
+        })
+        .map(|(package, key, account_id)| {
+            println!(
+                "Key {key} in {:?} / {} @ {} didn't work, account {account_id}",
+                package.source, package.name, package.version
+            );
+            (package.source, account_id)
+        })
+        .collect();
+    Ok((live_keys, unverified_keys, failures))
+}
+
+fn scan_file(scanner: &Scanner, package: &PackageToProcess) -> Result<Vec<ScannerMatch>> {
+    let name = &package.name;
+    let version = &package.version;
+    let source = &package.source;
+    let download = || {
+        scanner
+            .download_package(package)
+            .with_context(|| format!("Failed to download package {source:?} / {name} @ {version}"))
+    };
+    // Most packages don't contain anything that looks like a key, so they are checked as they are
+    // downloaded and only written to disk if the full check needs to run.
+    let result = match scanner.stream_quick_check(package) {
+        Ok(matches) if matches.is_empty() => Ok(None),
+        Ok(matches) => download().map(|downloaded_package| {
+            Some(PossiblyMatchedPackage {
+                downloaded_package,
+                matches,
+            })
+        }),
+        Err(e) if e.is::<SkipReason>() => Err(e),
+        Err(e) => {
+            eprintln!("Could not stream {source:?} / {name} @ {version}, downloading it: {e:#}");
+            scanner.quick_check(download()?).with_context(|| {
+                format!("Error running quick check on {source:?} / {name} @ {version}")
+            })
+        }
+    };
+    println!("Finished quick check on {source:?} / {name} @ {version}");
+    let Some(matched) = result? else {
+        return Ok(vec![]);
+    };
+
+    println!(
+        "running full check on {source:?} / {name} @ {version}\n - Previous match:\n{}",
+        matched
+            .matches
+            .iter()
+            .map(|v| format!(
+                "{}:{}:{}: {}",
+                v.path.display(),
+                v.line_number,
+                v.column,
+                v.text
+            ))
+            .join("\n\n")
+    );
+    scanner
+        .full_check(matched)
+        .with_context(|| format!("Error running full check on {source:?} / {name} @ {version}"))
+}
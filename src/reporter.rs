@@ -1,4 +1,4 @@
-use crate::aws::LiveKey;
+use crate::detectors::{self, LiveKey};
 use crate::scanners::ScannerMatch;
 use crate::sources::{PackageToProcess, SourceType};
 use anyhow::Result;
@@ -11,20 +11,25 @@ use tinytemplate::TinyTemplate;
 #[derive(Serialize)]
 struct TemplateContext {
     package: PackageToProcess,
-    findings: Vec<Finding>,
+    // Each finding rendered with the report template of the detector that found it.
+    findings: Vec<String>,
 }
 
 #[derive(Serialize)]
 struct Finding {
+    detector: String,
+    key: String,
     line_number: usize,
     file_path: String,
-    role_name: String,
-    access_key: String,
-    secret_key: String,
     public_url: Option<String>,
+    details: serde_json::Value,
 }
 
 fn url_for_finding(package: &PackageToProcess, scanner_match: &ScannerMatch) -> Option<String> {
+    // The inspector can't show files inside archives within a package.
+    if scanner_match.is_nested() {
+        return None;
+    }
     match package.source {
         SourceType::PyPi => {
             let public_path = format!(
@@ -33,7 +38,7 @@ fn url_for_finding(package: &PackageToProcess, scanner_match: &ScannerMatch) ->
                 package.version,
                 package.download_url.path().strip_prefix('/').unwrap(),
                 scanner_match.relative_path(),
-                scanner_match.rg_match.line_number
+                scanner_match.search_match.line_number
             );
             Some(public_path)
         }
@@ -46,6 +51,11 @@ pub fn create_findings(items: Vec<LiveKey>) -> Result<()> {
     template
         .add_template("markdown", include_str!("template.md"))
         .unwrap();
+    for detector in detectors::all() {
+        template
+            .add_template(detector.id(), detector.report_template())
+            .unwrap();
+    }
 
     // A single package may contain multiple keys. We ideally want a single file per release file,
     // So we need to sort and group the keys.
@@ -59,17 +69,18 @@ pub fn create_findings(items: Vec<LiveKey>) -> Result<()> {
             findings: v
                 .into_iter()
                 .map(|v| Finding {
+                    detector: v.scanner_match.detector.to_string(),
                     public_url: url_for_finding(
                         &v.scanner_match.downloaded_package.package,
                         &v.scanner_match,
                     ),
-                    line_number: v.scanner_match.rg_match.line_number,
-                    file_path: v.scanner_match.relative_path(),
-                    role_name: v.role_name,
-                    access_key: v.scanner_match.access_key,
-                    secret_key: v.scanner_match.secret_key,
+                    line_number: v.scanner_match.search_match.line_number,
+                    file_path: v.scanner_match.full_path(),
+                    key: v.scanner_match.key,
+                    details: v.scanner_match.details,
                 })
-                .collect(),
+                .map(|finding| template.render(&finding.detector, &finding))
+                .collect::<Result<_, _>>()?,
         };
         let rendered = template.render("markdown", &ctx)?;
 
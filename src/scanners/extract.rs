@@ -0,0 +1,413 @@
+use crate::sources::{PackageToProcess, SourceType};
+use anyhow::{bail, Context, Result};
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+use xz2::read::XzDecoder;
+use zip::read::read_zipfile_from_stream;
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+// The file type bits of a unix mode, and the value of them for symlinks.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+// Called with the path and contents of each file in an archive, returning `Break` to stop early.
+pub type Visitor<'a> = dyn FnMut(&Path, &mut dyn Read) -> Result<ControlFlow<()>> + 'a;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    // A tar file containing `data.tar.gz`, along with gzipped metadata.
+    Gem,
+    // A tar file containing `contents.tar.gz`, along with metadata.
+    HexTarball,
+}
+
+impl ArchiveFormat {
+    /// Works out the format of a downloaded package from its file name, falling back to the first
+    /// bytes of the file for names without a known extension, such as Packagist zipballs.
+    pub fn detect(package: &PackageToProcess, path: &Path) -> Result<Self> {
+        match Self::from_file_name(package) {
+            Some(format) => Ok(format),
+            None => Self::sniff(path)
+                .with_context(|| format!("Unknown archive format for {}", package.file_name())),
+        }
+    }
+
+    /// Works out the format of a package from its file name alone.
+    pub fn from_file_name(package: &PackageToProcess) -> Option<Self> {
+        Self::from_extension(package.file_name(), package.source == SourceType::HexPm)
+    }
+
+    /// Works out the format of an archive within a package from its path.
+    pub fn from_member_path(path: &Path) -> Option<Self> {
+        Self::from_extension(path.file_name()?.to_str()?, false)
+    }
+
+    fn from_extension(file_name: &str, is_hex_tarball: bool) -> Option<Self> {
+        let has_extension = |extensions: &[&str]| extensions.iter().any(|e| file_name.ends_with(e));
+        let format = if has_extension(&[".zip", ".whl", ".egg", ".jar", ".nupkg"]) {
+            ArchiveFormat::Zip
+        } else if has_extension(&[".tar.gz", ".tgz", ".crate"]) {
+            ArchiveFormat::TarGz
+        } else if has_extension(&[".tar.bz2"]) {
+            ArchiveFormat::TarBz2
+        } else if has_extension(&[".tar.xz"]) {
+            ArchiveFormat::TarXz
+        } else if has_extension(&[".gem"]) {
+            ArchiveFormat::Gem
+        } else if has_extension(&[".tar"]) && is_hex_tarball {
+            ArchiveFormat::HexTarball
+        } else if has_extension(&[".tar"]) {
+            ArchiveFormat::Tar
+        } else {
+            return None;
+        };
+        Some(format)
+    }
+
+    fn sniff(path: &Path) -> Result<Self> {
+        let mut header = vec![];
+        File::open(path)?.take(262).read_to_end(&mut header)?;
+        let format = if header.starts_with(b"PK\x03\x04") {
+            ArchiveFormat::Zip
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            ArchiveFormat::TarGz
+        } else if header.starts_with(b"BZh") {
+            ArchiveFormat::TarBz2
+        } else if header.starts_with(b"\xfd7zXZ\x00") {
+            ArchiveFormat::TarXz
+        } else if header.get(257..262) == Some(b"ustar") {
+            ArchiveFormat::Tar
+        } else {
+            bail!("Unrecognised file header");
+        };
+        Ok(format)
+    }
+}
+
+/// Calls `visit` with the path and contents of every file in an archive. Directories and links
+/// are skipped, and member paths are made relative to the archive. Stops once `visit` returns
+/// `Break`.
+pub fn visit_members(
+    format: ArchiveFormat,
+    path: &Path,
+    mut visit: impl FnMut(&Path, &mut dyn Read) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    let file = BufReader::new(
+        File::open(path).with_context(|| format!("Error opening {}", path.display()))?,
+    );
+    match format {
+        ArchiveFormat::Zip => visit_zip(file, &mut visit),
+        _ => visit_stream(format, file, visit),
+    }
+}
+
+/// Like `visit_members`, but reads the archive from a stream such as an HTTP response. Zip files
+/// are read using their local headers rather than the central directory at the end of the file,
+/// which fails for members that only record their size after their contents.
+pub fn visit_stream(
+    format: ArchiveFormat,
+    reader: impl Read,
+    mut visit: impl FnMut(&Path, &mut dyn Read) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    match format {
+        ArchiveFormat::Zip => visit_zip_stream(reader, &mut visit),
+        ArchiveFormat::Tar => visit_tar(reader, &mut visit),
+        ArchiveFormat::TarGz => visit_tar(GzDecoder::new(reader), &mut visit),
+        ArchiveFormat::TarBz2 => visit_tar(BzDecoder::new(reader), &mut visit),
+        ArchiveFormat::TarXz => visit_tar(XzDecoder::new(reader), &mut visit),
+        ArchiveFormat::Gem => visit_inner_tar_gz(reader, "data.tar.gz", &mut visit),
+        ArchiveFormat::HexTarball => visit_inner_tar_gz(reader, "contents.tar.gz", &mut visit),
+    }
+}
+
+/// Like `visit_members`, but reads an archive that has been loaded into memory.
+pub fn visit_bytes(
+    format: ArchiveFormat,
+    bytes: &[u8],
+    mut visit: impl FnMut(&Path, &mut dyn Read) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    match format {
+        ArchiveFormat::Zip => visit_zip(Cursor::new(bytes), &mut visit),
+        _ => visit_stream(format, bytes, visit),
+    }
+}
+
+fn visit_zip<R: Read + Seek>(reader: R, visit: &mut Visitor) -> Result<()> {
+    let mut archive = ZipArchive::new(reader).context("Error reading zip archive")?;
+    for index in 0..archive.len() {
+        let member = archive
+            .by_index(index)
+            .context("Error reading zip archive")?;
+        if visit_zip_member(member, visit)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn visit_zip_stream<R: Read>(mut reader: R, visit: &mut Visitor) -> Result<()> {
+    while let Some(member) =
+        read_zipfile_from_stream(&mut reader).context("Error reading zip archive")?
+    {
+        if visit_zip_member(member, visit)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn visit_zip_member(mut member: ZipFile, visit: &mut Visitor) -> Result<ControlFlow<()>> {
+    let is_symlink = member
+        .unix_mode()
+        .is_some_and(|mode| mode & S_IFMT == S_IFLNK);
+    if !member.is_file() || is_symlink {
+        return Ok(ControlFlow::Continue(()));
+    }
+    match safe_path(Path::new(member.name())) {
+        Some(path) => visit(&path, &mut member),
+        None => Ok(ControlFlow::Continue(())),
+    }
+}
+
+fn visit_tar<R: Read>(reader: R, visit: &mut Visitor) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().context("Error reading tar archive")? {
+        let mut entry = entry.context("Error reading tar archive")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(path) = safe_path(&entry.path()?) else {
+            continue;
+        };
+        if visit(&path, &mut entry)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Gems and Hex tarballs are a tar file with the package contents in a nested `.tar.gz` member.
+fn visit_inner_tar_gz<R: Read>(reader: R, inner: &str, visit: &mut Visitor) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().context("Error reading tar archive")? {
+        let entry = entry.context("Error reading tar archive")?;
+        if entry.path()?.as_os_str() == inner {
+            return visit_tar(GzDecoder::new(entry), visit)
+                .with_context(|| format!("Error reading {inner}"));
+        }
+    }
+    bail!("Archive does not contain {inner}")
+}
+
+// Nothing is extracted to disk, so a member path that would escape the archive is harmless, but
+// it's resolved within the archive so that reported paths are relative to it. Skipping the package
+// instead would let anyone avoid being scanned with a single `..` member. Empty paths are ignored.
+fn safe_path(path: &Path) -> Option<PathBuf> {
+    let mut safe = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => safe.push(part),
+            Component::ParentDir => {
+                safe.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    (!safe.as_os_str().is_empty()).then_some(safe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const FILES: &[(&str, &str)] = &[("pkg/setup.py", "setup()"), ("pkg/mod/__init__.py", "")];
+
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (path, contents) in files {
+            writer
+                .start_file(*path, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn bzip2(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // A tar file with the package contents gzipped in the `inner` member, like a gem.
+    fn outer_tar(inner: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, contents) in [("metadata.gz", gzip(b"")), (inner, gzip(&tar(FILES)))] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn read_members(format: ArchiveFormat, bytes: &[u8]) -> Vec<(String, String)> {
+        let mut members = vec![];
+        visit_bytes(format, bytes, |path, contents| {
+            let mut text = String::new();
+            contents.read_to_string(&mut text)?;
+            members.push((path.to_string_lossy().to_string(), text));
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        members
+    }
+
+    fn expected() -> Vec<(String, String)> {
+        FILES
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_every_format() {
+        let archives = [
+            (ArchiveFormat::Zip, zip(FILES)),
+            (ArchiveFormat::Tar, tar(FILES)),
+            (ArchiveFormat::TarGz, gzip(&tar(FILES))),
+            (ArchiveFormat::TarBz2, bzip2(&tar(FILES))),
+            (ArchiveFormat::TarXz, xz(&tar(FILES))),
+            (ArchiveFormat::Gem, outer_tar("data.tar.gz")),
+            (ArchiveFormat::HexTarball, outer_tar("contents.tar.gz")),
+        ];
+        for (format, bytes) in archives {
+            assert_eq!(read_members(format, &bytes), expected(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn streams_zip_from_local_headers() {
+        let mut members = vec![];
+        visit_stream(ArchiveFormat::Zip, zip(FILES).as_slice(), |path, _| {
+            members.push(path.to_path_buf());
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(
+            members,
+            [
+                PathBuf::from("pkg/setup.py"),
+                PathBuf::from("pkg/mod/__init__.py")
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_when_visitor_breaks() {
+        let mut visited = 0;
+        visit_bytes(ArchiveFormat::TarGz, &gzip(&tar(FILES)), |_, _| {
+            visited += 1;
+            Ok(ControlFlow::Break(()))
+        })
+        .unwrap();
+        assert_eq!(visited, 1);
+    }
+
+    #[test]
+    fn detects_formats_from_member_paths() {
+        let cases = [
+            ("vendor/x-1.0-py3-none-any.whl", Some(ArchiveFormat::Zip)),
+            ("vendor/x-1.0.tar.gz", Some(ArchiveFormat::TarGz)),
+            ("vendor/x-1.0.tar.bz2", Some(ArchiveFormat::TarBz2)),
+            ("vendor/x.gem", Some(ArchiveFormat::Gem)),
+            ("vendor/x.tar", Some(ArchiveFormat::Tar)),
+            ("vendor/x.py", None),
+        ];
+        for (path, format) in cases {
+            assert_eq!(ArchiveFormat::from_member_path(Path::new(path)), format);
+        }
+    }
+
+    #[test]
+    fn resolves_member_paths_within_archive() {
+        let cases = [
+            ("pkg/setup.py", Some("pkg/setup.py")),
+            ("./pkg/./setup.py", Some("pkg/setup.py")),
+            ("/etc/passwd", Some("etc/passwd")),
+            ("../../etc/passwd", Some("etc/passwd")),
+            ("pkg/../../setup.py", Some("setup.py")),
+            ("pkg/mod/../setup.py", Some("pkg/setup.py")),
+            ("", None),
+            ("pkg/..", None),
+        ];
+        for (path, safe) in cases {
+            assert_eq!(
+                safe_path(Path::new(path)),
+                safe.map(PathBuf::from),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn scans_archive_with_escaping_member() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for path in ["../evil.py", "pkg/setup.py"] {
+            zip.start_file(path, zip::write::FileOptions::default())
+                .unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+        let members: Vec<_> = read_members(ArchiveFormat::Zip, &bytes)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(members, ["evil.py", "pkg/setup.py"]);
+    }
+
+    #[test]
+    fn fails_without_inner_archive() {
+        let result = visit_bytes(ArchiveFormat::Gem, &tar(FILES), |_, _| {
+            Ok(ControlFlow::Continue(()))
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::scanners::ScannerConfig;
+use std::cell::{Cell, RefCell};
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Why a package was not scanned. Packages don't change once published, so these are recorded in
+/// the source stats rather than retried.
+#[derive(Debug)]
+pub enum SkipReason {
+    PackageTooLarge { size: u64, limit: u64 },
+    ExpandedTooLarge { limit: u64 },
+    TooManyFiles { limit: usize },
+    FileTooLarge { path: PathBuf, limit: u64 },
+    NestedArchiveTooLarge { path: PathBuf, limit: u64 },
+}
+
+impl SkipReason {
+    /// A short name for the reason, used as the key in the source stats.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SkipReason::PackageTooLarge { .. } => "package_too_large",
+            SkipReason::ExpandedTooLarge { .. } => "expanded_too_large",
+            SkipReason::TooManyFiles { .. } => "too_many_files",
+            SkipReason::FileTooLarge { .. } => "file_too_large",
+            SkipReason::NestedArchiveTooLarge { .. } => "nested_archive_too_large",
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::PackageTooLarge { size, limit } => {
+                write!(f, "package is {size} bytes, over the limit of {limit}")
+            }
+            SkipReason::ExpandedTooLarge { limit } => {
+                write!(f, "package expands to more than {limit} bytes")
+            }
+            SkipReason::TooManyFiles { limit } => {
+                write!(f, "package contains more than {limit} files")
+            }
+            SkipReason::FileTooLarge { path, limit } => {
+                write!(f, "{} is larger than {limit} bytes", path.display())
+            }
+            SkipReason::NestedArchiveTooLarge { path, limit } => {
+                write!(
+                    f,
+                    "nested archive {} is larger than {limit} bytes",
+                    path.display()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SkipReason {}
+
+/// Tracks how much of a package has been read during a single check. Once a limit is exceeded,
+/// reads fail and `finish` returns the reason in place of whatever error that caused.
+pub struct Budget<'a> {
+    config: &'a ScannerConfig,
+    expanded: Cell<u64>,
+    files: Cell<usize>,
+    exceeded: RefCell<Option<SkipReason>>,
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(config: &'a ScannerConfig) -> Self {
+        Self {
+            config,
+            expanded: Cell::new(0),
+            files: Cell::new(0),
+            exceeded: RefCell::new(None),
+        }
+    }
+
+    /// Counts a file in the package, returning a reader for its contents that enforces the size
+    /// limits.
+    pub fn file<'r>(
+        &'r self,
+        path: &Path,
+        contents: &'r mut dyn Read,
+    ) -> io::Result<LimitedReader<'r, 'a>> {
+        self.files.set(self.files.get() + 1);
+        if self.files.get() > self.config.max_files {
+            return Err(self.exceed(SkipReason::TooManyFiles {
+                limit: self.config.max_files,
+            }));
+        }
+        Ok(LimitedReader {
+            budget: self,
+            path: path.to_path_buf(),
+            read: 0,
+            inner: contents,
+        })
+    }
+
+    pub fn finish<T>(&self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        match self.exceeded.take() {
+            Some(reason) => Err(reason.into()),
+            None => result,
+        }
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded.borrow().is_some()
+    }
+
+    fn exceed(&self, reason: SkipReason) -> io::Error {
+        let error = io::Error::other(reason.to_string());
+        self.exceeded.borrow_mut().get_or_insert(reason);
+        error
+    }
+}
+
+pub struct LimitedReader<'r, 'a> {
+    budget: &'r Budget<'a>,
+    path: PathBuf,
+    read: u64,
+    inner: &'r mut dyn Read,
+}
+
+impl Read for LimitedReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        let budget = self.budget;
+        budget.expanded.set(budget.expanded.get() + read as u64);
+
+        let config = budget.config;
+        if self.read > config.max_file_size {
+            return Err(budget.exceed(SkipReason::FileTooLarge {
+                path: self.path.clone(),
+                limit: config.max_file_size,
+            }));
+        }
+        if budget.expanded.get() > config.max_expanded_size {
+            return Err(budget.exceed(SkipReason::ExpandedTooLarge {
+                limit: config.max_expanded_size,
+            }));
+        }
+        Ok(read)
+    }
+}
@@ -1,40 +1,30 @@
-mod ripgrep;
+mod extract;
+mod limits;
+mod search;
 
-pub use crate::scanners::ripgrep::{run_ripgrep, RipGrepMatch};
+use crate::detectors::{self, Extractor};
+use crate::scanners::extract::{visit_bytes, visit_members, visit_stream, ArchiveFormat, Visitor};
+use crate::scanners::limits::Budget;
+pub use crate::scanners::limits::SkipReason;
+use crate::scanners::search::find_first;
+pub use crate::scanners::search::{find_all, QuickCheck, SearchMatch};
 use crate::sources::PackageToProcess;
-use anyhow::Result;
-use itertools::Itertools;
-use lazy_static::lazy_static;
-use regex::Regex;
+use anyhow::{anyhow, bail, Context, Result};
 use std::fs::File;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 use temp_dir::TempDir;
 
-// Search for anything that looks like an AWS access key ID
-const QUICK_CHECK_REGEX: &str = "((?:ASIA|AKIA|AROA|AIDA)([A-Z0-7]{16}))";
-
-// This is a bit ridiculous, but it searches for the AWS access key pattern above combined with
-// the secret key regex ([a-zA-Z0-9+/]{40}). This regex is too general, so we need to pair it with
-// an access key match that is between 0 and 4 lines of the secret key match.
-// It only looks for keys surrounded by quotes, else the false positive rate is too large.
-// It also supports secret keys being defined before the access key.
-const FULL_CHECK_REGEX: &str = "(('|\")((?:ASIA|AKIA|AROA|AIDA)([A-Z0-7]{16}))('|\").*?(\n^.*?){0,4}(('|\")[a-zA-Z0-9+/]{40}('|\"))+|('|\")[a-zA-Z0-9+/]{40}('|\").*?(\n^.*?){0,3}('|\")((?:ASIA|AKIA|AROA|AIDA)([A-Z0-7]{16}))('|\"))+";
-
-// Two regular expressions to extract access keys from the matches.
-lazy_static! {
-    static ref ACCESS_KEY_REGEX: Regex =
-        Regex::new("(('|\")(?:ASIA|AKIA|AROA|AIDA)([A-Z0-7]{16})('|\"))").unwrap();
-    static ref SECRET_KEY_REGEX: Regex = Regex::new("(('|\")([a-zA-Z0-9+/]{40})('|\"))").unwrap();
-}
+const NESTED_SEPARATOR: &str = "!/";
 
 #[derive(Debug, Clone)]
 pub struct DownloadedPackage {
     pub package: PackageToProcess,
     _temp_dir: TempDir,
-    extract_dir: PathBuf,
     download_path: PathBuf,
+    format: ArchiveFormat,
 }
 
 impl PartialEq for DownloadedPackage {
@@ -46,57 +36,107 @@ impl PartialEq for DownloadedPackage {
 #[derive(Debug)]
 pub struct PossiblyMatchedPackage {
     pub downloaded_package: DownloadedPackage,
-    pub matches: Vec<RipGrepMatch>,
+    pub matches: Vec<SearchMatch>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ScannerMatch {
+    // The id of the detector that found the key.
+    pub detector: &'static str,
     pub downloaded_package: DownloadedPackage,
-    pub rg_match: RipGrepMatch,
-    pub access_key: String,
-    pub secret_key: String,
+    pub search_match: SearchMatch,
+    // Identifies the credential in logs and reports, such as an AWS access key ID. A package may
+    // have several matches with the same key, each a different guess at the rest of it.
+    pub key: String,
+    // How likely the parts of the credential are to belong together. Higher scoring matches are
+    // verified first.
+    pub score: i64,
+    // The rest of the credential and anything else the detector found with it, which only the
+    // detector understands. Its report template renders this as `details`.
+    pub details: serde_json::Value,
 }
 
 impl ScannerMatch {
+    /// The path of the file within the package. Files within nested archives have the path of
+    /// each archive joined with `!/`, such as `vendor/x.whl!/mod/config.py`.
     pub fn relative_path(&self) -> String {
-        self.rg_match
-            .path
-            .to_str()
-            .unwrap()
-            .strip_prefix(self.downloaded_package.extract_dir.to_str().unwrap())
-            .unwrap()
-            .strip_prefix('/')
-            .unwrap()
-            .to_string()
+        self.search_match.path.to_string_lossy().to_string()
+    }
+
+    /// The path of the file including the package it's in, such as
+    /// `pkg.tar.gz!/vendor/x.whl!/mod/config.py`.
+    pub fn full_path(&self) -> String {
+        format!(
+            "{}{NESTED_SEPARATOR}{}",
+            self.downloaded_package.package.file_name(),
+            self.relative_path()
+        )
+    }
+
+    pub fn is_nested(&self) -> bool {
+        self.relative_path().contains(NESTED_SEPARATOR)
     }
 }
 
 impl PartialEq for ScannerMatch {
     fn eq(&self, other: &Self) -> bool {
-        self.access_key == other.access_key
-            && self.secret_key == other.secret_key
+        self.detector == other.detector
+            && self.key == other.key
+            && self.details == other.details
             && self.downloaded_package == other.downloaded_package
     }
 }
 
-pub struct Scanner {}
+#[derive(clap::Args, Debug, Clone)]
+pub struct ScannerConfig {
+    /// How many levels of archives within a package to scan, such as wheels vendored in an sdist.
+    /// 0 only scans the files directly within the package
+    #[clap(long, default_value_t = 3)]
+    pub max_nesting_depth: usize,
+    /// Nested archives are read into memory, so packages containing larger ones are skipped
+    #[clap(long, default_value_t = 100 * 1024 * 1024)]
+    pub max_nested_archive_size: u64,
+    /// Packages larger than this many bytes are skipped without being downloaded, if the registry
+    /// or server tells us their size
+    #[clap(long, default_value_t = 1024 * 1024 * 1024)]
+    pub max_package_size: u64,
+    /// Packages that expand to more than this many bytes are skipped
+    #[clap(long, default_value_t = 4 * 1024 * 1024 * 1024)]
+    pub max_expanded_size: u64,
+    /// Packages containing more than this many files are skipped
+    #[clap(long, default_value_t = 100_000)]
+    pub max_files: usize,
+    /// Packages containing a file larger than this many bytes are skipped
+    #[clap(long, default_value_t = 512 * 1024 * 1024)]
+    pub max_file_size: u64,
+}
+
+pub struct Scanner {
+    config: ScannerConfig,
+    quick_checks: Vec<&'static QuickCheck>,
+}
 
 impl Scanner {
+    pub fn new(config: ScannerConfig) -> Self {
+        let quick_checks = detectors::all().iter().map(|d| d.quick_check()).collect();
+        Self {
+            config,
+            quick_checks,
+        }
+    }
+
     pub fn quick_check(
         &self,
         package: DownloadedPackage,
     ) -> Result<Option<PossiblyMatchedPackage>> {
-        let matches = run_ripgrep(&[
-            "--pre",
-            "./scripts/extract-stdout.sh",
-            QUICK_CHECK_REGEX,
-            "--threads",
-            "1",
-            "-m",
-            "1",
-            "--json",
-            package.download_path.to_str().unwrap(),
-        ])?;
+        let budget = Budget::new(&self.config);
+        let mut matches = vec![];
+        let result = visit_members(package.format, &package.download_path, |path, contents| {
+            self.visit_nested(&budget, path, contents, 0, &mut |path, contents| {
+                quick_check_member(&self.quick_checks, &mut matches, path, contents)
+            })
+        });
+        budget.finish(result)?;
         if matches.is_empty() {
             Ok(None)
         } else {
@@ -107,77 +147,178 @@ impl Scanner {
         }
     }
 
+    /// Runs the quick check as the package is downloaded, without writing it to disk. This fails
+    /// for packages that can't be read as a stream, such as those with an unknown extension or zip
+    /// files that only record the size of a member after its contents, which should be downloaded
+    /// and checked with `quick_check` instead, unless the error is a `SkipReason`.
+    pub fn stream_quick_check(&self, package: &PackageToProcess) -> Result<Vec<SearchMatch>> {
+        let format = ArchiveFormat::from_file_name(package)
+            .ok_or_else(|| anyhow!("Unknown archive format for {}", package.file_name()))?;
+        let reader = self.open_package(package)?;
+        let budget = Budget::new(&self.config);
+        let mut matches = vec![];
+        // Without a Content-Length the size isn't known up front, so the download is cut off at
+        // the limit. Reading the truncated archive fails, and `download_package` then skips it.
+        let reader = reader.take(self.config.max_package_size);
+        let result = visit_stream(format, reader, |path, contents| {
+            self.visit_nested(&budget, path, contents, 0, &mut |path, contents| {
+                quick_check_member(&self.quick_checks, &mut matches, path, contents)
+            })
+        });
+        budget.finish(result)?;
+        Ok(matches)
+    }
+
     pub fn full_check(&self, package: PossiblyMatchedPackage) -> Result<Vec<ScannerMatch>> {
-        extract_package(&package.downloaded_package)?;
-        let matches = run_ripgrep(&[
-            "--multiline",
-            "-o",
-            FULL_CHECK_REGEX,
-            "--json",
-            package.downloaded_package.extract_dir.to_str().unwrap(),
-        ])?;
-        println!("matches: {matches:?}");
-        let mut matched_keys = vec![];
-        // The output may contain multiple matches for our second-stage regex.
-        // Here we create a cartesian product product of all matches.
-        for rg_match in &matches {
-            let matches = ACCESS_KEY_REGEX
-                .find_iter(&rg_match.lines)
-                .cartesian_product(
-                    SECRET_KEY_REGEX
-                        .find_iter(&rg_match.lines)
-                        .collect::<Vec<_>>(),
-                )
-                .map(|(key, secret)| (trim_quotes(key.as_str()), trim_quotes(secret.as_str())));
-
-            matched_keys.extend(
-                matches
-                    .into_iter()
-                    .map(|(access_key, secret_key)| ScannerMatch {
-                        downloaded_package: package.downloaded_package.clone(),
-                        rg_match: rg_match.clone(),
-                        access_key,
-                        secret_key,
-                    }),
-            )
+        let downloaded = &package.downloaded_package;
+        let budget = Budget::new(&self.config);
+        let mut extractors: Vec<Box<dyn Extractor>> =
+            detectors::all().iter().map(|d| d.extractor()).collect();
+        let result = visit_members(
+            downloaded.format,
+            &downloaded.download_path,
+            |path, contents| {
+                self.visit_nested(&budget, path, contents, 0, &mut |path, contents| {
+                    let mut buffer = vec![];
+                    contents
+                        .read_to_end(&mut buffer)
+                        .with_context(|| format!("Error reading {}", path.display()))?;
+                    for extractor in extractors.iter_mut() {
+                        extractor.visit(path, &buffer);
+                    }
+                    Ok(ControlFlow::Continue(()))
+                })
+            },
+        );
+        budget.finish(result)?;
+        Ok(extractors
+            .into_iter()
+            .flat_map(|extractor| extractor.finish(downloaded))
+            .collect())
+    }
+
+    // Calls `visit` with a file in a package, or with each file inside it if it's an archive and
+    // we haven't reached the maximum depth. Archives that can't be read are scanned as a file.
+    fn visit_nested(
+        &self,
+        budget: &Budget,
+        path: &Path,
+        contents: &mut dyn Read,
+        depth: usize,
+        visit: &mut Visitor,
+    ) -> Result<ControlFlow<()>> {
+        let mut contents = budget.file(path, contents)?;
+        let format = ArchiveFormat::from_member_path(path);
+        let Some(format) = format.filter(|_| depth < self.config.max_nesting_depth) else {
+            return visit(path, &mut contents);
+        };
+
+        let max_size = self.config.max_nested_archive_size;
+        let mut archive = vec![];
+        contents
+            .take(max_size + 1)
+            .read_to_end(&mut archive)
+            .with_context(|| format!("Error reading {}", path.display()))?;
+        if archive.len() as u64 > max_size {
+            bail!(SkipReason::NestedArchiveTooLarge {
+                path: path.to_path_buf(),
+                limit: max_size,
+            });
+        }
+
+        let mut flow = ControlFlow::Continue(());
+        let result = visit_bytes(format, &archive, |member_path, contents| {
+            let nested_path = format!(
+                "{}{NESTED_SEPARATOR}{}",
+                path.display(),
+                member_path.display()
+            );
+            flow =
+                self.visit_nested(budget, Path::new(&nested_path), contents, depth + 1, visit)?;
+            Ok(flow)
+        });
+        if let Err(e) = result {
+            if e.is::<SkipReason>() || budget.is_exceeded() {
+                return Err(e);
+            }
+            eprintln!("Error reading nested archive {}: {e:#}", path.display());
+            return visit(path, &mut archive.as_slice());
         }
+        Ok(flow)
+    }
+
+    // Opens a package for reading, after checking that it's not too large.
+    fn open_package(&self, package: &PackageToProcess) -> Result<Box<dyn Read>> {
+        let (size, reader): (_, Box<dyn Read>) = if package.download_url.scheme() == "file" {
+            let path = package
+                .download_url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file URL {}", package.download_url))?;
+            let file = File::open(path)?;
+            (Some(file.metadata()?.len()), Box::new(file))
+        } else {
+            self.check_size(package.size)?;
+            let client = reqwest::blocking::Client::new();
+            let request = package.authenticate(client.get(package.download_url.clone()));
+            let response = request.send()?.error_for_status()?;
+            (response.content_length(), Box::new(response))
+        };
+        self.check_size(size)?;
+        Ok(reader)
+    }
 
-        Ok(matched_keys)
+    fn check_size(&self, size: Option<u64>) -> Result<(), SkipReason> {
+        let limit = self.config.max_package_size;
+        match size {
+            Some(size) if size > limit => Err(SkipReason::PackageTooLarge { size, limit }),
+            _ => Ok(()),
+        }
     }
 
     pub fn download_package(&self, package: &PackageToProcess) -> Result<DownloadedPackage> {
         let temp_dir = TempDir::new()?;
         let temp_dir_path = temp_dir.path();
         let download_dir = temp_dir_path.join("download");
-        let extract_dir = temp_dir_path.join("extracted");
-        fs::create_dir_all(&extract_dir)?;
         fs::create_dir_all(&download_dir)?;
 
         let download_path = download_dir.join(package.file_name());
 
+        let max_size = self.config.max_package_size;
         let mut out = File::create(&download_path)?;
-        let mut resp =
-            reqwest::blocking::get(package.download_url.to_string())?.error_for_status()?;
-        io::copy(&mut resp, &mut out)?;
+        let written = io::copy(
+            &mut self.open_package(package)?.take(max_size + 1),
+            &mut out,
+        )?;
+        if written > max_size {
+            bail!(SkipReason::PackageTooLarge {
+                size: written,
+                limit: max_size,
+            });
+        }
+        let format = ArchiveFormat::detect(package, &download_path)?;
         Ok(DownloadedPackage {
             package: package.clone(),
             _temp_dir: temp_dir,
-            extract_dir,
             download_path,
+            format,
         })
     }
 }
 
-fn extract_package(package: &DownloadedPackage) -> Result<()> {
-    Command::new("./scripts/extract-fs.sh")
-        .args([
-            package.extract_dir.to_str().unwrap(),
-            package.download_path.to_str().unwrap(),
-        ])
-        .output()?;
-    Ok(())
-}
-
-fn trim_quotes(string: &str) -> String {
-    string[1..string.len() - 1].to_string()
+// Only the first match is needed, so this stops reading the package once there is one.
+fn quick_check_member(
+    quick_checks: &[&QuickCheck],
+    matches: &mut Vec<SearchMatch>,
+    path: &Path,
+    contents: &mut dyn Read,
+) -> Result<ControlFlow<()>> {
+    let found = find_first(path, contents, quick_checks)
+        .with_context(|| format!("Error reading {}", path.display()))?;
+    match found {
+        Some(found) => {
+            matches.push(found);
+            Ok(ControlFlow::Break(()))
+        }
+        None => Ok(ControlFlow::Continue(())),
+    }
 }
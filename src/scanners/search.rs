@@ -0,0 +1,194 @@
+use aho_corasick::AhoCorasick;
+use regex::bytes::{Match, Regex};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Chunks of this size are read from a file when looking for the first match in it.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What a detector looks for when deciding whether a package needs the full check.
+pub struct QuickCheck {
+    // Every match contains one of these, so files without them can be skipped without running the
+    // more expensive regular expression over them.
+    prefilter: AhoCorasick,
+    regex: Regex,
+    // Matches up to this many bytes long are found even if they span two chunks.
+    max_match_len: usize,
+}
+
+impl QuickCheck {
+    pub fn new(literals: &[&str], regex: &str, max_match_len: usize) -> Self {
+        Self {
+            prefilter: AhoCorasick::new(literals).unwrap(),
+            regex: Regex::new(regex).unwrap(),
+            max_match_len,
+        }
+    }
+
+    /// Whether `contents` contains any of the literals that every match contains.
+    pub fn might_match(&self, contents: &[u8]) -> bool {
+        self.prefilter.is_match(contents)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    // The text of the match.
+    pub text: String,
+    // The byte offset of the start of the match within the file.
+    pub offset: usize,
+    // The 1-based line and column of the start of the match.
+    pub line_number: usize,
+    pub column: usize,
+    // The path of the file within the package.
+    pub path: PathBuf,
+}
+
+// A position within a file, which is moved forward through it so each byte is only counted once
+// no matter how many matches there are.
+struct LinePosition {
+    offset: usize,
+    // The 1-based number of the line at `offset`, and the offset of the start of that line.
+    line_number: usize,
+    line_start: usize,
+}
+
+impl LinePosition {
+    fn new() -> Self {
+        LinePosition {
+            offset: 0,
+            line_number: 1,
+            line_start: 0,
+        }
+    }
+
+    // Moves forward to `offset`, given a buffer holding the file from `buffer_offset` onwards,
+    // which must include the current position.
+    fn advance(&mut self, buffer: &[u8], buffer_offset: usize, offset: usize) {
+        let skipped = &buffer[self.offset - buffer_offset..offset - buffer_offset];
+        if let Some(i) = skipped.iter().rposition(|b| *b == b'\n') {
+            self.line_number += count_lines(skipped);
+            self.line_start = self.offset + i + 1;
+        }
+        self.offset = offset;
+    }
+
+    // Moves forward to a match in a buffer holding the file from `buffer_offset` onwards.
+    fn search_match(
+        &mut self,
+        path: &Path,
+        buffer: &[u8],
+        buffer_offset: usize,
+        m: Match,
+    ) -> SearchMatch {
+        self.advance(buffer, buffer_offset, buffer_offset + m.start());
+        SearchMatch {
+            text: String::from_utf8_lossy(m.as_bytes()).to_string(),
+            offset: self.offset,
+            line_number: self.line_number,
+            column: self.offset - self.line_start + 1,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+/// Returns the first match of any of `checks` in `contents`, without reading all of it into memory.
+pub fn find_first(
+    path: &Path,
+    contents: &mut dyn Read,
+    checks: &[&QuickCheck],
+) -> io::Result<Option<SearchMatch>> {
+    let max_match_len = checks.iter().map(|c| c.max_match_len).max().unwrap_or(0);
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE + max_match_len);
+    // The offset within the file of the start of `buffer`, and the position of it.
+    let mut buffer_offset = 0;
+    let mut position = LinePosition::new();
+    loop {
+        let read = (&mut *contents)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut buffer)?;
+        let found = checks
+            .iter()
+            .filter(|c| c.might_match(&buffer))
+            .find_map(|c| c.regex.find(&buffer));
+        if let Some(m) = found {
+            return Ok(Some(position.search_match(path, &buffer, buffer_offset, m)));
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        // Keep the end of the chunk, in case a match starts in it.
+        let keep_from = buffer.len().saturating_sub(max_match_len);
+        position.advance(&buffer, buffer_offset, buffer_offset + keep_from);
+        buffer_offset += keep_from;
+        buffer.drain(..keep_from);
+    }
+}
+
+/// Returns every match of `regex` in `contents`.
+pub fn find_all(path: &Path, contents: &[u8], regex: &Regex) -> Vec<SearchMatch> {
+    let mut position = LinePosition::new();
+    regex
+        .find_iter(contents)
+        .map(|m| position.search_match(path, contents, 0, m))
+        .collect()
+}
+
+fn count_lines(buffer: &[u8]) -> usize {
+    buffer.iter().filter(|b| **b == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AKIAAAAAAAAAAAAAAAA1";
+
+    fn quick_check() -> QuickCheck {
+        QuickCheck::new(&["AKIA"], "AKIA[A-Z0-7]{16}", KEY.len())
+    }
+
+    fn find(contents: &str) -> Option<SearchMatch> {
+        find_first(
+            Path::new("file.py"),
+            &mut contents.as_bytes(),
+            &[&quick_check()],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_match_spanning_two_chunks() {
+        let padding = "x".repeat(CHUNK_SIZE - KEY.len() / 2);
+        let found = find(&format!("{padding}{KEY}")).unwrap();
+        assert_eq!(found.text, KEY);
+        assert_eq!(found.offset, padding.len());
+        assert_eq!((found.line_number, found.column), (1, padding.len() + 1));
+    }
+
+    #[test]
+    fn counts_lines_across_chunks() {
+        let line = format!("{}\n", "x".repeat(99));
+        let lines = CHUNK_SIZE / line.len() + 10;
+        let contents = format!("{}  {KEY}\n", line.repeat(lines));
+        let found = find(&contents).unwrap();
+        assert_eq!(found.offset, line.len() * lines + 2);
+        assert_eq!((found.line_number, found.column), (lines + 1, 3));
+    }
+
+    #[test]
+    fn finds_nothing_without_match() {
+        assert!(find(&"AKIA lowercase akiaaaaaaaaaaaaaaaaa1\n".repeat(5000)).is_none());
+    }
+
+    #[test]
+    fn finds_all_matches_with_positions() {
+        let contents = format!("a = '{KEY}'\n\nb = 1; c = '{KEY}'\n");
+        let regex = Regex::new("AKIA[A-Z0-7]{16}").unwrap();
+        let positions: Vec<_> = find_all(Path::new("file.py"), contents.as_bytes(), &regex)
+            .into_iter()
+            .map(|m| (m.line_number, m.column, m.offset))
+            .collect();
+        assert_eq!(positions, [(1, 6, 5), (3, 13, 40)]);
+    }
+}
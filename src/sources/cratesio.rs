@@ -0,0 +1,200 @@
+use crate::sources::{get_json, PackageToProcess, Source, SourceStats, SourceType};
+use crate::state::SourceData;
+use anyhow::Result;
+use chrono::prelude::*;
+use chrono_humanize::HumanTime;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://crates.io/api/v1";
+const DOWNLOAD_URL: &str = "https://static.crates.io/crates";
+const PAGE_SIZE: usize = 100;
+// The crates.io crawler policy asks for at most one request per second:
+// https://crates.io/data-access#api
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+pub struct CratesIoSource {
+    last_package_timestamp: DateTime<Utc>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CratesResponse {
+    crates: Vec<CrateSummary>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CrateSummary {
+    name: String,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VersionsResponse {
+    versions: Vec<CrateVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CrateVersion {
+    #[serde(rename = "crate")]
+    name: String,
+    num: String,
+    created_at: DateTime<Utc>,
+    crate_size: Option<u64>,
+}
+
+impl Display for CratesIoSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CratesIo - Crates published after {} ({})",
+            self.last_package_timestamp,
+            HumanTime::from(self.last_package_timestamp - Utc::now())
+        )
+    }
+}
+
+impl Source for CratesIoSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            // Every crate updated since the cursor has to be fetched, so a new source starts from
+            // now rather than working through the whole history.
+            SourceData::Null => Ok(Self {
+                last_package_timestamp: Utc::now(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let mut results = fetch_versions(&client, self.last_package_timestamp, None)?;
+        results.truncate(limit);
+
+        if let Some(last) = results.last() {
+            self.last_package_timestamp = last.created_at;
+        }
+
+        let to_process = results.into_iter().map(package_for_version).collect();
+        Ok(to_process)
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let mut results = fetch_versions(&client, since, Some(until))?;
+        results.truncate(limit);
+        Ok(results.into_iter().map(package_for_version).collect())
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("{API_URL}/crates/{name}/versions");
+        let response: VersionsResponse = get_json(&client, &url)?;
+        Ok(response
+            .versions
+            .into_iter()
+            .filter(|v| version.is_none_or(|version| version == v.num))
+            .map(package_for_version)
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+// There's no feed of new versions, so this finds the crates updated since `after` and then every
+// version of each of them.
+fn fetch_versions(
+    client: &reqwest::blocking::Client,
+    after: DateTime<Utc>,
+    before: Option<DateTime<Utc>>,
+) -> Result<Vec<CrateVersion>> {
+    // Crates are sorted by the time they were last updated, newest first. Once we reach a crate
+    // that was last updated before `after`, none of the remaining crates have new versions. Every
+    // page up to there is needed, as the oldest new versions are on the last one.
+    let mut throttle = Throttle::default();
+    let mut updated_crates = vec![];
+    for page in 1.. {
+        let url = format!("{API_URL}/crates?sort=recent-updates&per_page={PAGE_SIZE}&page={page}");
+        throttle.wait();
+        let response: CratesResponse = get_json(client, &url)?;
+        let returned = response.crates.len();
+        let mut reached_cursor = false;
+        for krate in response.crates {
+            if krate.updated_at <= after {
+                reached_cursor = true;
+                break;
+            }
+            updated_crates.push(krate.name);
+        }
+        if reached_cursor || returned < PAGE_SIZE {
+            break;
+        }
+    }
+
+    // Unlike the other sources these are fetched sequentially, one per `REQUEST_INTERVAL`, to
+    // stay within the crates.io crawler policy.
+    println!(
+        "Fetching crates.io versions for {} crates",
+        updated_crates.len()
+    );
+    let mut results = vec![];
+    for name in updated_crates {
+        let url = format!("{API_URL}/crates/{name}/versions");
+        throttle.wait();
+        let response: VersionsResponse = get_json(client, &url)?;
+        results.extend(
+            response.versions.into_iter().filter(|v| {
+                v.created_at > after && before.is_none_or(|before| v.created_at < before)
+            }),
+        );
+    }
+    results.sort_by_key(|v| v.created_at);
+    Ok(results)
+}
+
+// Spaces requests at least `REQUEST_INTERVAL` apart.
+#[derive(Default)]
+struct Throttle {
+    last_request: Option<Instant>,
+}
+
+impl Throttle {
+    fn wait(&mut self) {
+        if let Some(last_request) = self.last_request {
+            std::thread::sleep(REQUEST_INTERVAL.saturating_sub(last_request.elapsed()));
+        }
+        self.last_request = Some(Instant::now());
+    }
+}
+
+fn package_for_version(version: CrateVersion) -> PackageToProcess {
+    // https://static.crates.io/crates/serde/serde-1.0.152.crate
+    let download_url = format!(
+        "{DOWNLOAD_URL}/{0}/{0}-{1}.crate",
+        version.name, version.num
+    )
+    .parse()
+    .unwrap();
+    PackageToProcess::new(
+        version.name,
+        version.num,
+        download_url,
+        SourceType::CratesIo,
+    )
+    .with_size(version.crate_size)
+}
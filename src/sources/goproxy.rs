@@ -0,0 +1,194 @@
+use crate::sources::{PackageToProcess, Source, SourceStats, SourceType, USER_AGENT};
+use crate::state::SourceData;
+use anyhow::{Context, Result};
+use chrono::prelude::*;
+use chrono_humanize::HumanTime;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+const INDEX_URL: &str = "https://index.golang.org/index";
+const PROXY_URL: &str = "https://proxy.golang.org";
+// The index will not return more than this many entries per request.
+const MAX_PAGE_SIZE: usize = 2000;
+
+#[derive(Serialize, Deserialize)]
+pub struct GoProxySource {
+    last_package_timestamp: DateTime<Utc>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct IndexEntry {
+    path: String,
+    version: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl Display for GoProxySource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GoProxy - Modules indexed after {} ({})",
+            self.last_package_timestamp,
+            HumanTime::from(self.last_package_timestamp - Utc::now())
+        )
+    }
+}
+
+impl Source for GoProxySource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                last_package_timestamp: "2023-01-01T00:00:00Z".parse().unwrap(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let results = fetch_index(&client, self.last_package_timestamp, None, limit)?;
+        if let Some(last) = results.last() {
+            self.last_package_timestamp = last.timestamp;
+        }
+
+        let to_process = results
+            .into_iter()
+            .map(|e| package_for_version(e.path, e.version))
+            .collect();
+        Ok(to_process)
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let results = fetch_index(&client, since, Some(until), limit)?;
+        Ok(results
+            .into_iter()
+            .map(|e| package_for_version(e.path, e.version))
+            .collect())
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let versions = match version {
+            Some(version) => vec![version.to_string()],
+            None => {
+                let url = format!("{PROXY_URL}/{}/@v/list", escape_module_path(name));
+                reqwest::blocking::Client::new()
+                    .get(&url)
+                    .header("User-Agent", USER_AGENT)
+                    .send()
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.text())
+                    .with_context(|| format!("Failed to request {url}"))?
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_string())
+                    .collect()
+            }
+        };
+        Ok(versions
+            .into_iter()
+            .map(|version| package_for_version(name.to_string(), version))
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+// Pages through the index from `after` until there are `limit` modules or one at `before`.
+fn fetch_index(
+    client: &reqwest::blocking::Client,
+    after: DateTime<Utc>,
+    before: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Result<Vec<IndexEntry>> {
+    let mut cursor = after;
+    let mut results: Vec<IndexEntry> = vec![];
+
+    while results.len() < limit {
+        let page_size = (limit - results.len()).min(MAX_PAGE_SIZE);
+        // `since` is inclusive, so the entry at our cursor is returned again and skipped below.
+        let url = format!(
+            "{INDEX_URL}?since={}&limit={page_size}",
+            cursor.to_rfc3339_opts(SecondsFormat::Nanos, true)
+        );
+        let text = client
+            .get(&url)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|r| r.text())
+            .with_context(|| format!("Failed to request {url}"))?;
+        // The index is a stream of newline-delimited JSON objects.
+        let entries: Vec<IndexEntry> = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Failed to parse JSON from {url}"))?;
+        let returned = entries.len();
+
+        let mut reached_end = returned < page_size;
+        let mut new_entries = vec![];
+        for entry in entries {
+            if before.is_some_and(|before| entry.timestamp >= before) {
+                reached_end = true;
+                break;
+            }
+            if entry.timestamp > cursor {
+                new_entries.push(entry);
+            }
+        }
+        match new_entries.last() {
+            Some(last) => cursor = last.timestamp,
+            None => break,
+        }
+        results.extend(new_entries);
+
+        if reached_end {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+fn package_for_version(path: String, version: String) -> PackageToProcess {
+    // https://proxy.golang.org/github.com/!azure/azure-sdk-for-go/@v/v1.0.0.zip
+    let download_url = format!(
+        "{PROXY_URL}/{}/@v/{}.zip",
+        escape_module_path(&path),
+        escape_module_path(&version)
+    )
+    .parse()
+    .unwrap();
+    PackageToProcess::new(path, version, download_url, SourceType::GoProxy)
+}
+
+// The proxy is served from case-insensitive storage, so uppercase letters in module paths and
+// versions are escaped as an exclamation mark followed by the lowercase letter.
+fn escape_module_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c.is_ascii_uppercase() {
+            escaped.push('!');
+            escaped.push(c.to_ascii_lowercase());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
@@ -0,0 +1,287 @@
+use crate::sources::USER_AGENT;
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use prost::Message;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha512};
+use std::fs;
+use std::io::Read;
+use url::Url;
+
+pub const HEX_PM_REPOSITORY_URL: &str = "https://repo.hex.pm";
+pub const HEX_PM_REPOSITORY_NAME: &str = "hexpm";
+
+// https://hex.pm/docs/public_keys
+pub const HEX_PM_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEApqREcFDt5vV21JVe2QNB
+Edvzk6w36aNFhVGWN5toNJRjRJ6m4hIuG4KaXtDWVLjnvct6MYMfqhC79HAGwyF+
+IqR6Q6a5bbFSsImgBJwz1oadoVKD6ZNetAuCIK84cjMrEFRkELtEIPNHblCzUkkM
+3rS9+DPlnfG8hBvGi6tvQIuZmXGCxF/73hU0/MyGhbmEjIKRtG6b0sJYKelRLTPW
+XgK7s5pESgiwf2YC/2MGDXjAJfpfCd0RpLdvd4eRiXtVlE9qO9bND94E7PgQ/xqZ
+J1i2xWFndWa6nfFnRxZmCStCOZWYYPlaxr+FZceFbpMwzTNs4g3d4tLNUcbKAIH4
+0wIDAQAB
+-----END PUBLIC KEY-----";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Signed {
+    #[prost(bytes = "vec", required, tag = "1")]
+    pub payload: Vec<u8>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub signature: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Names {
+    #[prost(message, repeated, tag = "1")]
+    pub packages: Vec<NamesPackage>,
+    #[prost(string, required, tag = "2")]
+    pub repository: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NamesPackage {
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub updated_at: Option<Timestamp>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, required, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, required, tag = "2")]
+    pub nanos: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Versions {
+    #[prost(message, repeated, tag = "1")]
+    pub packages: Vec<VersionsPackage>,
+    #[prost(string, required, tag = "2")]
+    pub repository: String,
+}
+
+/// A registry resource that names the repository it belongs to.
+pub trait RepositoryResource: Message + Default {
+    fn repository(&self) -> &str;
+}
+
+impl RepositoryResource for Names {
+    fn repository(&self) -> &str {
+        &self.repository
+    }
+}
+
+impl RepositoryResource for Versions {
+    fn repository(&self) -> &str {
+        &self.repository
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct VersionsPackage {
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(string, repeated, tag = "2")]
+    pub versions: Vec<String>,
+    #[prost(int32, repeated, packed = "true", tag = "3")]
+    pub retired: Vec<i32>,
+}
+
+/// Reads the resources of a Hex repository, which may be a `file://` URL pointing to a local
+/// mirror. Each resource is a gzipped `Signed` protobuf message wrapping a payload signed by the
+/// repository: https://github.com/hexpm/specifications/blob/main/registry-v2.md
+pub struct Registry {
+    url: Url,
+    // The name every resource must give for its repository, so that a mirror can't serve the
+    // resources of another repository signed with the same key.
+    name: String,
+    public_key: RsaPublicKey,
+    client: reqwest::blocking::Client,
+}
+
+impl Registry {
+    pub fn new(url: Url, name: &str, public_key_pem: &str) -> Result<Self> {
+        let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+            .context("Invalid Hex repository public key")?;
+        Ok(Self {
+            url,
+            name: name.to_string(),
+            public_key,
+            client: reqwest::blocking::Client::new(),
+        })
+    }
+
+    pub fn names(&self) -> Result<Names> {
+        self.fetch("names")
+    }
+
+    pub fn versions(&self) -> Result<Versions> {
+        self.fetch("versions")
+    }
+
+    pub fn tarball_url(&self, name: &str, version: &str) -> Result<Url> {
+        Ok(self.resource_url(&format!("tarballs/{name}-{version}.tar"))?)
+    }
+
+    fn resource_url(&self, path: &str) -> Result<Url, url::ParseError> {
+        let base = self.url.as_str().trim_end_matches('/');
+        format!("{base}/{path}").parse()
+    }
+
+    fn fetch<T: RepositoryResource>(&self, path: &str) -> Result<T> {
+        let url = self.resource_url(path)?;
+        let compressed = if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file URL {url}"))?;
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?
+        } else {
+            self.client
+                .get(url.clone())
+                .header("User-Agent", USER_AGENT)
+                .send()
+                .and_then(|r| r.error_for_status())
+                .and_then(|r| r.bytes())
+                .with_context(|| format!("Failed to request {url}"))?
+                .to_vec()
+        };
+
+        let mut decoded = vec![];
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .with_context(|| format!("Failed to decompress {url}"))?;
+        let signed = Signed::decode(decoded.as_slice())
+            .with_context(|| format!("Failed to decode {url}"))?;
+        self.verify(&signed)
+            .with_context(|| format!("Invalid signature for {url}"))?;
+        let resource = T::decode(signed.payload.as_slice())
+            .with_context(|| format!("Failed to decode {url}"))?;
+        if resource.repository() != self.name {
+            return Err(anyhow!(
+                "{url} is for repository {:?}, expected {:?}",
+                resource.repository(),
+                self.name
+            ));
+        }
+        Ok(resource)
+    }
+
+    fn verify(&self, signed: &Signed) -> Result<()> {
+        let signature = signed
+            .signature
+            .as_ref()
+            .ok_or_else(|| anyhow!("Resource is not signed"))?;
+        let hashed = Sha512::digest(&signed.payload);
+        self.public_key
+            .verify(Pkcs1v15Sign::new::<Sha512>(), &hashed, signature)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use lazy_static::lazy_static;
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    use rsa::RsaPrivateKey;
+    use std::io::Write;
+    use std::path::Path;
+    use temp_dir::TempDir;
+
+    // Generating keys is slow, so each test uses these.
+    lazy_static! {
+        static ref KEY: RsaPrivateKey = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        static ref OTHER_KEY: RsaPrivateKey =
+            RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    }
+
+    fn registry_for(key: &RsaPrivateKey) -> Registry {
+        let pem = key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        Registry::new("file:///registry".parse().unwrap(), "hexpm", &pem).unwrap()
+    }
+
+    fn sign(key: &RsaPrivateKey, payload: &[u8]) -> Signed {
+        let hashed = Sha512::digest(payload);
+        let signature = key.sign(Pkcs1v15Sign::new::<Sha512>(), &hashed).unwrap();
+        Signed {
+            payload: payload.to_vec(),
+            signature: Some(signature),
+        }
+    }
+
+    #[test]
+    fn accepts_signed_resource() {
+        let registry = registry_for(&KEY);
+        assert!(registry.verify(&sign(&KEY, b"names")).is_ok());
+    }
+
+    #[test]
+    fn rejects_modified_payload() {
+        let registry = registry_for(&KEY);
+        let mut signed = sign(&KEY, b"names");
+        signed.payload = b"other names".to_vec();
+        assert!(registry.verify(&signed).is_err());
+    }
+
+    #[test]
+    fn rejects_resource_signed_by_another_key() {
+        let registry = registry_for(&KEY);
+        assert!(registry.verify(&sign(&OTHER_KEY, b"names")).is_err());
+    }
+
+    #[test]
+    fn rejects_unsigned_resource() {
+        let registry = registry_for(&KEY);
+        let signed = Signed {
+            payload: b"names".to_vec(),
+            signature: None,
+        };
+        assert!(registry.verify(&signed).is_err());
+    }
+
+    // Writes `names` to a local mirror, signed by `KEY`, and returns a registry reading it.
+    fn mirror_with_names(dir: &Path, names: &Names) -> Registry {
+        let signed = sign(&KEY, &names.encode_to_vec());
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&signed.encode_to_vec()).unwrap();
+        fs::write(dir.join("names"), encoder.finish().unwrap()).unwrap();
+
+        let pem = KEY
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        let url = Url::from_directory_path(dir).unwrap();
+        Registry::new(url, "hexpm", &pem).unwrap()
+    }
+
+    #[test]
+    fn fetches_resource_for_repository() {
+        let dir = TempDir::new().unwrap();
+        let names = Names {
+            packages: vec![],
+            repository: "hexpm".to_string(),
+        };
+        let registry = mirror_with_names(dir.path(), &names);
+        assert_eq!(registry.names().unwrap(), names);
+    }
+
+    #[test]
+    fn rejects_resource_for_another_repository() {
+        let dir = TempDir::new().unwrap();
+        let names = Names {
+            packages: vec![],
+            repository: "other".to_string(),
+        };
+        let registry = mirror_with_names(dir.path(), &names);
+        let error = registry.names().unwrap_err();
+        assert!(error.to_string().contains("expected \"hexpm\""));
+    }
+}
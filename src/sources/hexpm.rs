@@ -1,24 +1,61 @@
-use crate::sources::{PackageToProcess, Source, SourceStats, SourceType};
+use crate::sources::hex_registry::{
+    Registry, HEX_PM_PUBLIC_KEY, HEX_PM_REPOSITORY_NAME, HEX_PM_REPOSITORY_URL,
+};
+use crate::sources::{PackageToProcess, Source, SourceStats, SourceType, USER_AGENT};
 use crate::state::SourceData;
-use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use chrono::prelude::*;
 
 use chrono_humanize::HumanTime;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Display, Formatter};
+use url::Url;
 
 #[derive(Serialize, Deserialize)]
 pub struct HexPmSource {
     last_package_timestamp: DateTime<Utc>,
+    // The name@version of releases inserted at exactly `last_package_timestamp` that we have already
+    // processed, so that none are skipped or processed twice when `limit` falls between them.
+    #[serde(default)]
+    seen_at_timestamp: BTreeSet<String>,
+    #[serde(default)]
+    mode: HexPmMode,
+    // The repository read in `registry` mode, defaulting to repo.hex.pm. This may be a `file://`
+    // URL pointing to a local mirror.
+    #[serde(default)]
+    registry_url: Option<Url>,
+    // The name of the repository, which its resources must match, defaulting to `hexpm`.
+    #[serde(default)]
+    registry_name: Option<String>,
+    // The PEM encoded public key of the repository, defaulting to that of repo.hex.pm.
+    #[serde(default)]
+    registry_public_key: Option<String>,
+    // Package name -> every version we have seen in the registry.
+    #[serde(default)]
+    registry_snapshot: BTreeMap<String, BTreeSet<String>>,
     #[serde(default)]
     stats: SourceStats,
 }
 
+/// How we discover new releases. `api` pages through the packages API sorted by update time, while
+/// `registry` diffs the signed `/names` and `/versions` registry resources against a snapshot of
+/// every version we have seen, so no release can be missed during bursts.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HexPmMode {
+    #[default]
+    Api,
+    Registry,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct HexPmResponse {
     name: String,
+    updated_at: DateTime<Utc>,
     releases: Vec<HexPmRelease>,
 }
 
@@ -28,14 +65,154 @@ pub struct HexPmRelease {
     inserted_at: DateTime<Utc>,
 }
 
+impl HexPmSource {
+    fn registry(&self) -> Result<Registry> {
+        let url = match &self.registry_url {
+            Some(url) => url.clone(),
+            None => HEX_PM_REPOSITORY_URL.parse()?,
+        };
+        let public_key = self
+            .registry_public_key
+            .as_deref()
+            .unwrap_or(HEX_PM_PUBLIC_KEY);
+        let name = self
+            .registry_name
+            .as_deref()
+            .unwrap_or(HEX_PM_REPOSITORY_NAME);
+        Registry::new(url, name, public_key)
+    }
+
+    fn packages_from_api(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let fetched = fetch_releases(self.last_package_timestamp, None)?;
+        let to_process = self
+            .take_new_releases(fetched, limit)
+            .into_iter()
+            .map(|(name, release)| release_to_package(name, release))
+            .collect();
+        Ok(to_process)
+    }
+
+    // Returns the first `limit` new releases from `fetched` in insertion order, and moves the
+    // cursor past them.
+    fn take_new_releases(
+        &mut self,
+        fetched: Vec<(String, HexPmRelease)>,
+        limit: usize,
+    ) -> Vec<(String, HexPmRelease)> {
+        // Releases are fetched from the cursor inclusive, so those on it are returned again. A
+        // package updated while we page through the API can also appear on two pages.
+        let mut results: Vec<_> = fetched
+            .into_iter()
+            .filter(|(name, r)| {
+                r.inserted_at > self.last_package_timestamp
+                    || (r.inserted_at == self.last_package_timestamp
+                        && !self
+                            .seen_at_timestamp
+                            .contains(&format!("{name}@{}", r.version)))
+            })
+            .unique_by(|(name, r)| format!("{name}@{}", r.version))
+            .sorted_by(|(a_name, a), (b_name, b)| {
+                (a.inserted_at, a_name, &a.version).cmp(&(b.inserted_at, b_name, &b.version))
+            })
+            .collect();
+        results.truncate(limit);
+
+        if let Some((_, last)) = results.last() {
+            let last_inserted_at = last.inserted_at;
+            if last_inserted_at != self.last_package_timestamp {
+                self.last_package_timestamp = last_inserted_at;
+                self.seen_at_timestamp.clear();
+            }
+            self.seen_at_timestamp.extend(
+                results
+                    .iter()
+                    .filter(|(_, r)| r.inserted_at == last_inserted_at)
+                    .map(|(name, r)| format!("{name}@{}", r.version)),
+            );
+        }
+        results
+    }
+
+    fn packages_from_registry(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let registry = self.registry()?;
+        let names = registry.names()?;
+        let versions = registry.versions()?;
+
+        // Without a snapshot we can't tell which versions are new, and we don't want to scan the
+        // entire repository. So the first run just records every version that currently exists.
+        if self.registry_snapshot.is_empty() {
+            self.registry_snapshot = versions
+                .packages
+                .into_iter()
+                .map(|p| (p.name, p.versions.into_iter().collect()))
+                .collect();
+            println!(
+                "Created Hex registry snapshot of {} packages",
+                self.registry_snapshot.len()
+            );
+            return Ok(vec![]);
+        }
+
+        // Process the least recently updated packages first, so that if we hit our limit the rest
+        // are picked up on the next run.
+        let updated_at: HashMap<_, _> = names
+            .packages
+            .into_iter()
+            .map(|p| {
+                let updated_at = p.updated_at.map(|t| (t.seconds, t.nanos));
+                (p.name, updated_at.unwrap_or_default())
+            })
+            .collect();
+        let packages = versions.packages.into_iter().sorted_by_key(|p| {
+            let updated_at = updated_at.get(&p.name).copied().unwrap_or_default();
+            (updated_at, p.name.clone())
+        });
+
+        let mut to_process = vec![];
+        for package in packages {
+            let seen = self
+                .registry_snapshot
+                .entry(package.name.clone())
+                .or_default();
+            for version in package.versions {
+                if to_process.len() >= limit {
+                    return Ok(to_process);
+                }
+                if seen.contains(&version) {
+                    continue;
+                }
+                to_process.push(PackageToProcess::new(
+                    package.name.clone(),
+                    version.clone(),
+                    registry.tarball_url(&package.name, &version)?,
+                    SourceType::HexPm,
+                ));
+                seen.insert(version);
+            }
+        }
+        Ok(to_process)
+    }
+}
+
 impl Display for HexPmSource {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "HexPm - Packages updated from {} ({})",
-            self.last_package_timestamp,
-            HumanTime::from(self.last_package_timestamp - Utc::now())
-        )
+        match self.mode {
+            HexPmMode::Api => write!(
+                f,
+                "HexPm - Packages updated from {} ({})",
+                self.last_package_timestamp,
+                HumanTime::from(self.last_package_timestamp - Utc::now())
+            ),
+            HexPmMode::Registry => write!(
+                f,
+                "HexPm - Registry {} ({} packages in snapshot)",
+                self.registry_url
+                    .as_ref()
+                    .map(|u| u.as_str())
+                    .unwrap_or(HEX_PM_REPOSITORY_URL),
+                self.registry_snapshot.len()
+            ),
+        }
     }
 }
 
@@ -44,6 +221,12 @@ impl Source for HexPmSource {
         match data {
             SourceData::Null => Ok(Self {
                 last_package_timestamp: "2018-01-01T00:00:00Z".parse().unwrap(),
+                seen_at_timestamp: Default::default(),
+                mode: Default::default(),
+                registry_url: None,
+                registry_name: None,
+                registry_public_key: None,
+                registry_snapshot: Default::default(),
                 stats: Default::default(),
             }),
             _ => Ok(serde_json::from_value(data)?),
@@ -51,68 +234,44 @@ impl Source for HexPmSource {
     }
 
     fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
-        let base_url = "https://hex.pm/api/packages?sort=updated_at&search=".to_string();
-        let mut results = vec![];
-        let client = reqwest::blocking::Client::new();
-
-        // Hex pages start at 1
-        for page in 1..20 {
-            if results.len() >= limit {
-                break;
-            }
-            let url = format!("{base_url}&page={page}");
-            // ToDo: Replace the user agent with a link to the repo
-            let response = client
-                .get(url)
-                .header("User-Agent", "aws-key-finder")
-                .send()?;
-            let hex_response: Vec<HexPmResponse> = response.json()?;
-
-            if hex_response.is_empty() {
-                break;
-            } else {
-                results.extend(hex_response.into_iter().flat_map(|v| {
-                    let new_releases = v
-                        .releases
-                        .into_iter()
-                        .filter(|r| r.inserted_at >= self.last_package_timestamp);
-                    new_releases
-                        .map(|r| (v.name.clone(), r))
-                        .collect::<Vec<_>>()
-                }));
-            }
+        match self.mode {
+            HexPmMode::Api => self.packages_from_api(limit),
+            HexPmMode::Registry => self.packages_from_registry(limit),
         }
+    }
 
-        let results: Vec<_> = results.into_iter().rev().take(limit).collect();
-
-        let last_updated_at = results
-            .iter()
-            .map(|(_, r)| r.inserted_at)
-            .max_by_key(|v| *v)
-            .ok_or_else(|| anyhow!("No gem releases found"))?;
-
-        self.last_package_timestamp = last_updated_at;
-
-        let to_process = results
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let to_process = fetch_releases(since, Some(until))?
             .into_iter()
-            .map(|(name, release)| {
-                PackageToProcess {
-                    // https://repo.hex.pm/tarballs/mathlogic_s3_test-0.1.0.tar
-                    download_url: format!(
-                        "https://repo.hex.pm/tarballs/{}-{}.tar",
-                        name, release.version
-                    )
-                    .parse()
-                    .unwrap(),
-                    name,
-                    version: release.version,
-                    source: SourceType::HexPm,
-                }
-            })
+            .take(limit)
+            .map(|(name, release)| release_to_package(name, release))
             .collect();
         Ok(to_process)
     }
 
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("https://hex.pm/api/packages/{name}");
+        let response: HexPmResponse = client
+            .get(&url)
+            .header("User-Agent", USER_AGENT)
+            .send()?
+            .error_for_status()?
+            .json()
+            .with_context(|| format!("Failed to parse JSON from {url}"))?;
+        Ok(response
+            .releases
+            .into_iter()
+            .filter(|r| version.is_none_or(|version| version == r.version))
+            .map(|release| release_to_package(name.to_string(), release))
+            .collect())
+    }
+
     fn to_state(&self) -> Result<SourceData> {
         Ok(serde_json::to_value(self)?)
     }
@@ -121,3 +280,151 @@ impl Source for HexPmSource {
         &mut self.stats
     }
 }
+
+// Returns the releases inserted from `since` and before `before`, sorted by (inserted_at, name,
+// version).
+fn fetch_releases(
+    since: DateTime<Utc>,
+    before: Option<DateTime<Utc>>,
+) -> Result<Vec<(String, HexPmRelease)>> {
+    let base_url = "https://hex.pm/api/packages?sort=updated_at&search=".to_string();
+    let mut results = vec![];
+    let client = reqwest::blocking::Client::new();
+
+    // Packages are sorted by the time they were last updated, newest first. A package is updated
+    // when a release is inserted, so once we reach a package that was last updated before `since`
+    // none of the remaining packages have releases we're interested in.
+    // Hex pages start at 1
+    for page in 1.. {
+        let url = format!("{base_url}&page={page}");
+        let response = client
+            .get(&url)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to request {url}"))?;
+        let hex_response: Vec<HexPmResponse> = response
+            .json()
+            .with_context(|| format!("Failed to parse JSON from {url}"))?;
+
+        if hex_response.is_empty() {
+            break;
+        }
+        let reached_since = hex_response.iter().any(|v| v.updated_at < since);
+        results.extend(hex_response.into_iter().flat_map(|v| {
+            let new_releases = v.releases.into_iter().filter(|r| {
+                r.inserted_at >= since && before.is_none_or(|before| r.inserted_at < before)
+            });
+            new_releases
+                .map(|r| (v.name.clone(), r))
+                .collect::<Vec<_>>()
+        }));
+        if reached_since {
+            break;
+        }
+    }
+
+    results.sort_by(|(a_name, a), (b_name, b)| {
+        (a.inserted_at, a_name, &a.version).cmp(&(b.inserted_at, b_name, &b.version))
+    });
+    Ok(results)
+}
+
+fn release_to_package(name: String, release: HexPmRelease) -> PackageToProcess {
+    PackageToProcess {
+        // https://repo.hex.pm/tarballs/mathlogic_s3_test-0.1.0.tar
+        download_url: format!(
+            "https://repo.hex.pm/tarballs/{}-{}.tar",
+            name, release.version
+        )
+        .parse()
+        .unwrap(),
+        name,
+        version: release.version,
+        source: SourceType::HexPm,
+        size: None,
+        authenticated: false,
+        file_name: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(name: &str, version: &str, inserted_at: &str) -> (String, HexPmRelease) {
+        (
+            name.to_string(),
+            HexPmRelease {
+                version: version.to_string(),
+                inserted_at: inserted_at.parse().unwrap(),
+            },
+        )
+    }
+
+    fn ids(releases: &[(String, HexPmRelease)]) -> Vec<String> {
+        releases
+            .iter()
+            .map(|(name, r)| format!("{name}@{}", r.version))
+            .collect()
+    }
+
+    fn source() -> HexPmSource {
+        let mut source = HexPmSource::new(SourceData::Null).unwrap();
+        source.last_package_timestamp = "2023-01-01T00:00:00Z".parse().unwrap();
+        source
+    }
+
+    fn fetched() -> Vec<(String, HexPmRelease)> {
+        vec![
+            release("a", "1.0.0", "2023-01-01T00:00:01Z"),
+            release("a", "1.0.1", "2023-01-01T00:00:01Z"),
+            release("b", "1.0.0", "2023-01-01T00:00:01Z"),
+            release("c", "1.0.0", "2023-01-01T00:00:02Z"),
+        ]
+    }
+
+    #[test]
+    fn limit_between_releases_with_the_same_timestamp() {
+        let mut source = source();
+
+        let first = source.take_new_releases(fetched(), 2);
+        assert_eq!(ids(&first), ["a@1.0.0", "a@1.0.1"]);
+        assert_eq!(
+            source.last_package_timestamp,
+            "2023-01-01T00:00:01Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // Releases are fetched from the cursor inclusive, so the same ones come back.
+        let second = source.take_new_releases(fetched(), 2);
+        assert_eq!(ids(&second), ["b@1.0.0", "c@1.0.0"]);
+        assert_eq!(
+            source.seen_at_timestamp,
+            BTreeSet::from(["c@1.0.0".to_string()])
+        );
+
+        assert!(source.take_new_releases(fetched(), 2).is_empty());
+    }
+
+    #[test]
+    fn releases_returned_twice_are_taken_once() {
+        let mut source = source();
+        let mut fetched = fetched();
+        fetched.push(release("b", "1.0.0", "2023-01-01T00:00:01Z"));
+
+        let taken = source.take_new_releases(fetched, 10);
+        assert_eq!(ids(&taken), ["a@1.0.0", "a@1.0.1", "b@1.0.0", "c@1.0.0"]);
+    }
+
+    #[test]
+    fn releases_before_the_cursor_are_skipped() {
+        let mut source = source();
+        let fetched = vec![
+            release("old", "1.0.0", "2022-12-31T23:59:59Z"),
+            release("new", "1.0.0", "2023-01-01T00:00:00Z"),
+        ];
+
+        let taken = source.take_new_releases(fetched, 10);
+        assert_eq!(ids(&taken), ["new@1.0.0"]);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::sources::{
+    parse_file_name, PackageToProcess, Source, SourceStats, SourceType, ARCHIVE_EXTENSIONS,
+};
+use crate::state::SourceData;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Scans archives in a local directory, such as release artifacts before they are published. The
+/// directory is stored in the state file, or taken from `LOCAL_SOURCE_PATH` if it isn't set.
+#[derive(Serialize, Deserialize)]
+pub struct LocalSource {
+    path: Option<PathBuf>,
+    // Path -> fingerprint of the file when we last scanned it. Changed files are scanned again.
+    #[serde(default)]
+    scanned_files: BTreeMap<PathBuf, FileFingerprint>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct FileFingerprint {
+    size: u64,
+    modified: DateTime<Utc>,
+}
+
+impl LocalSource {
+    fn path(&self) -> Result<PathBuf> {
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => Ok(env::var("LOCAL_SOURCE_PATH")
+                .context("No path in the state and LOCAL_SOURCE_PATH is not set")?
+                .into()),
+        }
+    }
+}
+
+impl Display for LocalSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.path() {
+            Ok(path) => write!(
+                f,
+                "Local - Archives under {} ({} files scanned)",
+                path.display(),
+                self.scanned_files.len()
+            ),
+            Err(_) => write!(f, "Local - No path configured"),
+        }
+    }
+}
+
+impl Source for LocalSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                path: None,
+                scanned_files: Default::default(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let root = self.path()?;
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Error reading directory {}", root.display()))?;
+        let mut archives = vec![];
+        find_archives(&root, &mut archives)?;
+        archives.sort();
+
+        let mut to_process = vec![];
+        for path in archives {
+            if to_process.len() >= limit {
+                break;
+            }
+            let metadata = fs::metadata(&path)?;
+            let fingerprint = FileFingerprint {
+                size: metadata.len(),
+                modified: metadata.modified()?.into(),
+            };
+            if self.scanned_files.get(&path) == Some(&fingerprint) {
+                continue;
+            }
+
+            to_process.push(to_package(path.clone())?);
+            self.scanned_files.insert(path, fingerprint);
+        }
+        Ok(to_process)
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let root = self.path()?;
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Error reading directory {}", root.display()))?;
+        let mut archives = vec![];
+        find_archives(&root, &mut archives)?;
+        archives.sort();
+
+        let mut to_process = vec![];
+        for path in archives {
+            if to_process.len() >= limit {
+                break;
+            }
+            let modified: DateTime<Utc> = fs::metadata(&path)?.modified()?.into();
+            if modified >= since && modified < until {
+                to_process.push(to_package(path)?);
+            }
+        }
+        Ok(to_process)
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let mut archives = vec![];
+        find_archives(&self.path()?, &mut archives)?;
+        archives
+            .into_iter()
+            .filter_map(|path| {
+                let (file_name, file_version) = package_name(&path);
+                (file_name == name && version.is_none_or(|version| version == file_version))
+                    .then(|| to_package(path))
+            })
+            .collect()
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+fn find_archives(dir: &Path, archives: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Error reading directory {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            find_archives(&path, archives)?;
+        } else if file_type.is_file() {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if ARCHIVE_EXTENSIONS
+                .iter()
+                .any(|ext| file_name.ends_with(ext))
+            {
+                archives.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn package_name(path: &Path) -> (String, String) {
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    parse_file_name(&file_name).unwrap_or((file_name, String::new()))
+}
+
+fn to_package(path: PathBuf) -> Result<PackageToProcess> {
+    let (name, version) = package_name(&path);
+    let url = Url::from_file_path(&path)
+        .map_err(|_| anyhow!("Cannot create a URL for {}", path.display()))?;
+    let size = fs::metadata(&path)?.len();
+    Ok(PackageToProcess::new(name, version, url, SourceType::Local).with_size(Some(size)))
+}
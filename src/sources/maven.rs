@@ -0,0 +1,186 @@
+use crate::sources::{get_json, PackageToProcess, Source, SourceStats, SourceType};
+use crate::state::SourceData;
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use chrono_humanize::HumanTime;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use url::Url;
+
+const SEARCH_URL: &str = "https://search.maven.org/solrsearch/select";
+const REPOSITORY_URL: &str = "https://repo1.maven.org/maven2";
+const PAGE_SIZE: usize = 200;
+
+// The files of an artifact that we scan, identified by their "classifier + extension" suffix.
+const SCANNED_SUFFIXES: &[&str] = &[".jar", "-sources.jar"];
+
+#[derive(Serialize, Deserialize)]
+pub struct MavenSource {
+    last_package_timestamp: DateTime<Utc>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResponse {
+    response: SearchResults,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResults {
+    docs: Vec<MavenArtifact>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MavenArtifact {
+    g: String,
+    a: String,
+    v: String,
+    // Milliseconds since the epoch
+    timestamp: i64,
+    // The available files, as "classifier + extension" suffixes. i.e `-sources.jar`
+    #[serde(default)]
+    ec: Vec<String>,
+}
+
+impl MavenArtifact {
+    fn deployed_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.timestamp).unwrap()
+    }
+}
+
+impl Display for MavenSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Maven - Artifacts deployed after {} ({})",
+            self.last_package_timestamp,
+            HumanTime::from(self.last_package_timestamp - Utc::now())
+        )
+    }
+}
+
+impl Source for MavenSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                last_package_timestamp: "2023-01-01T00:00:00Z".parse().unwrap(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let results = fetch_artifacts(&client, self.last_package_timestamp, None, limit)?;
+
+        if let Some(last) = results.last().map(|a| a.deployed_at()) {
+            self.last_package_timestamp = last;
+        }
+
+        let to_process = results.iter().flat_map(packages_for_artifact).collect();
+        Ok(to_process)
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        Ok(fetch_artifacts(&client, since, Some(until), limit)?
+            .iter()
+            .flat_map(packages_for_artifact)
+            .collect())
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let (group, artifact) = name
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Maven packages must be given as group:artifact"))?;
+        let mut query = format!("g:\"{group}\" AND a:\"{artifact}\"");
+        if let Some(version) = version {
+            query.push_str(&format!(" AND v:\"{version}\""));
+        }
+        let mut url = Url::parse(SEARCH_URL)?;
+        url.query_pairs_mut()
+            .append_pair("q", &query)
+            .append_pair("core", "gav")
+            .append_pair("wt", "json")
+            .append_pair("rows", &PAGE_SIZE.to_string());
+        let response: SearchResponse = get_json(&reqwest::blocking::Client::new(), url.as_str())?;
+        Ok(response
+            .response
+            .docs
+            .iter()
+            .flat_map(packages_for_artifact)
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+// Queries the artifacts deployed after `after` and before `before` oldest first, so that paging
+// stops at `limit` rather than having to reach the cursor from the newest artifact.
+fn fetch_artifacts(
+    client: &reqwest::blocking::Client,
+    after: DateTime<Utc>,
+    before: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Result<Vec<MavenArtifact>> {
+    let before = before.map_or("*".to_string(), |b| b.timestamp_millis().to_string());
+    let query = format!("timestamp:{{{} TO {before}}}", after.timestamp_millis());
+    let mut results = vec![];
+    while results.len() < limit {
+        let mut url = Url::parse(SEARCH_URL)?;
+        url.query_pairs_mut()
+            .append_pair("q", &query)
+            .append_pair("core", "gav")
+            .append_pair("sort", "timestamp asc")
+            .append_pair("wt", "json")
+            .append_pair("rows", &PAGE_SIZE.to_string())
+            .append_pair("start", &results.len().to_string());
+        let response: SearchResponse = get_json(client, url.as_str())?;
+        let returned = response.response.docs.len();
+        results.extend(response.response.docs);
+        if returned < PAGE_SIZE {
+            break;
+        }
+    }
+    results.truncate(limit);
+    Ok(results)
+}
+
+fn packages_for_artifact(artifact: &MavenArtifact) -> Vec<PackageToProcess> {
+    let name = format!("{}:{}", artifact.g, artifact.a);
+    let base_url = format!(
+        "{REPOSITORY_URL}/{}/{}/{}",
+        artifact.g.replace('.', "/"),
+        artifact.a,
+        artifact.v
+    );
+    artifact
+        .ec
+        .iter()
+        .filter(|suffix| SCANNED_SUFFIXES.contains(&suffix.as_str()))
+        .map(|suffix| {
+            // https://repo1.maven.org/maven2/com/google/guava/guava/31.1-jre/guava-31.1-jre-sources.jar
+            PackageToProcess::new(
+                name.clone(),
+                artifact.v.clone(),
+                format!("{base_url}/{}-{}{suffix}", artifact.a, artifact.v)
+                    .parse()
+                    .unwrap(),
+                SourceType::Maven,
+            )
+        })
+        .collect()
+}
@@ -1,18 +1,39 @@
+mod cratesio;
+mod goproxy;
+mod hex_registry;
 mod hexpm;
+mod local;
+mod maven;
+mod npm;
+mod nuget;
+mod packagist;
 mod pypi;
 mod rubygems;
+mod simple_index;
 
 use crate::state::SourceData;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fmt::Display;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
 use url::Url;
 
+pub use cratesio::CratesIoSource;
+pub use goproxy::GoProxySource;
 pub use hexpm::HexPmSource;
+pub use local::LocalSource;
+pub use maven::MavenSource;
+pub use npm::NpmSource;
+pub use nuget::NuGetSource;
+pub use packagist::PackagistSource;
 pub use pypi::PyPiSource;
 pub use rubygems::RubyGemsSource;
+pub use simple_index::SimpleIndexSource;
 
 #[derive(
     Deserialize, Serialize, Debug, Clone, Hash, Ord, PartialOrd, Eq, PartialEq, clap::ValueEnum,
@@ -22,6 +43,14 @@ pub enum SourceType {
     PyPi,
     RubyGems,
     HexPm,
+    Npm,
+    NuGet,
+    Packagist,
+    GoProxy,
+    CratesIo,
+    Maven,
+    SimpleIndex,
+    Local,
 }
 
 impl SourceType {
@@ -30,6 +59,14 @@ impl SourceType {
             SourceType::PyPi => Box::new(PyPiSource::new(data)?),
             SourceType::RubyGems => Box::new(RubyGemsSource::new(data)?),
             SourceType::HexPm => Box::new(HexPmSource::new(data)?),
+            SourceType::Npm => Box::new(NpmSource::new(data)?),
+            SourceType::NuGet => Box::new(NuGetSource::new(data)?),
+            SourceType::Packagist => Box::new(PackagistSource::new(data)?),
+            SourceType::GoProxy => Box::new(GoProxySource::new(data)?),
+            SourceType::CratesIo => Box::new(CratesIoSource::new(data)?),
+            SourceType::Maven => Box::new(MavenSource::new(data)?),
+            SourceType::SimpleIndex => Box::new(SimpleIndexSource::new(data)?),
+            SourceType::Local => Box::new(LocalSource::new(data)?),
         })
     }
 
@@ -39,16 +76,35 @@ impl SourceType {
             SourceType::PyPi => root.join("pypi"),
             SourceType::RubyGems => root.join("rubygems"),
             SourceType::HexPm => root.join("elixir"),
+            SourceType::Npm => root.join("npm"),
+            SourceType::NuGet => root.join("nuget"),
+            SourceType::Packagist => root.join("packagist"),
+            SourceType::GoProxy => root.join("go"),
+            SourceType::CratesIo => root.join("crates"),
+            SourceType::Maven => root.join("maven"),
+            SourceType::SimpleIndex => root.join("simple"),
+            SourceType::Local => root.join("local"),
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PackageToProcess {
     pub download_url: Url,
     pub name: String,
     pub version: String,
     pub source: SourceType,
+    // The size of the file according to the registry, if it tells us.
+    #[serde(default)]
+    pub size: Option<u64>,
+    // Whether the source's credentials may be sent when downloading the file. Only files hosted
+    // on the same origin as the private index that lists them are downloaded with credentials.
+    #[serde(default)]
+    pub authenticated: bool,
+    // Used instead of the last segment of the download URL, for sources whose URLs don't end in
+    // the file name.
+    #[serde(default)]
+    pub file_name: Option<String>,
 }
 
 impl PackageToProcess {
@@ -58,15 +114,169 @@ impl PackageToProcess {
             name,
             version,
             source,
+            size: None,
+            authenticated: false,
+            file_name: None,
+        }
+    }
+
+    pub fn with_size(mut self, size: Option<u64>) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_authentication(mut self, authenticated: bool) -> Self {
+        self.authenticated = authenticated;
+        self
+    }
+
+    pub fn with_file_name(mut self, file_name: String) -> Self {
+        self.file_name = Some(file_name);
+        self
+    }
+
+    /// Adds any credentials needed to download the file.
+    pub fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.source {
+            SourceType::SimpleIndex if self.authenticated => simple_index::authenticate(request),
+            _ => request,
         }
     }
 
     pub fn file_name(&self) -> &str {
-        self.download_url
-            .path_segments()
-            .expect("PackageToProcess empty path segments")
-            .last()
-            .unwrap()
+        match &self.file_name {
+            Some(file_name) => file_name,
+            None => self
+                .download_url
+                .path_segments()
+                .expect("PackageToProcess empty path segments")
+                .next_back()
+                .unwrap(),
+        }
+    }
+}
+
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".tar.gz", ".tar.bz2", ".tar.xz", ".tgz", ".zip", ".whl", ".egg", ".gem", ".tar", ".crate",
+    ".nupkg", ".jar",
+];
+
+/// Splits a package file name such as `requests-2.28.1.tar.gz` or
+/// `requests-2.28.1-py3-none-any.whl` into its name and version.
+pub fn parse_file_name(file_name: &str) -> Option<(String, String)> {
+    let stem = ARCHIVE_EXTENSIONS
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))?;
+    // Wheels and eggs use `-` to separate their tags, and escape it within the name and version.
+    if file_name.ends_with(".whl") || file_name.ends_with(".egg") {
+        let mut parts = stem.split('-');
+        return Some((parts.next()?.to_string(), parts.next()?.to_string()));
+    }
+    // Everything else is `{name}-{version}`, where the name may contain `-` but the version
+    // starts with a digit.
+    let split = stem
+        .match_indices('-')
+        .find(|(idx, _)| stem[idx + 1..].starts_with(|c: char| c.is_ascii_digit()))?
+        .0;
+    Some((stem[..split].to_string(), stem[split + 1..].to_string()))
+}
+
+// Some registries, such as crates.io, reject requests without a user agent.
+pub const USER_AGENT: &str = "https://github.com/orf/aws-creds-scanner";
+
+pub fn get_json<T: DeserializeOwned>(client: &Client, url: &str) -> Result<T> {
+    client
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to request {url}"))?
+        .json()
+        .with_context(|| format!("Failed to parse JSON from {url}"))
+}
+
+/// Returned by sources that don't support a way of finding packages, such as backfilling, so
+/// they can be left out of a run that includes other sources.
+#[derive(Debug)]
+pub struct Unsupported(pub &'static str);
+
+impl Display for Unsupported {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "This source does not support {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+// Packages that haven't had a release queued for this many days are forgotten by `ReleaseCursor`.
+const RELEASE_RETENTION_DAYS: i64 = 30;
+
+/// Finds new releases for sources whose change feed only says which packages changed. Each change
+/// lists every release of its package, and a package can be processed long after newer releases
+/// of other packages, so each package has its own cursor. A release is new if it was published
+/// after both `published_after` and the newest release queued for its package.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReleaseCursor {
+    // Moves forward as packages are forgotten, as it filters out the same releases they did.
+    published_after: Option<DateTime<Utc>>,
+    // The publish time of the newest release queued for each recently changed package.
+    #[serde(default)]
+    package_timestamps: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl ReleaseCursor {
+    /// A cursor that ignores releases published before `published_after`, such as the time a
+    /// source is created so its first run doesn't queue every release of every package it sees.
+    pub fn starting_at(published_after: Option<DateTime<Utc>>) -> Self {
+        Self {
+            published_after,
+            package_timestamps: Default::default(),
+        }
+    }
+
+    pub fn published_after(&self) -> Option<DateTime<Utc>> {
+        self.published_after
+    }
+
+    pub fn tracked_packages(&self) -> usize {
+        self.package_timestamps.len()
+    }
+
+    pub fn is_new(&self, package: &str, published: DateTime<Utc>) -> bool {
+        self.published_after.is_none_or(|after| published > after)
+            && self
+                .package_timestamps
+                .get(package)
+                .is_none_or(|last| published > *last)
+    }
+
+    pub fn record(&mut self, package: &str, published: DateTime<Utc>) {
+        let last = self
+            .package_timestamps
+            .entry(package.to_string())
+            .or_insert(published);
+        *last = (*last).max(published);
+    }
+
+    /// Forgets packages without a release queued in the retention period, and moves
+    /// `published_after` up to it. This is only done once every change in the feed has been
+    /// processed, as a change still to be processed could list releases from before the cutoff,
+    /// no matter how old.
+    pub fn forget_old_packages(&mut self, caught_up: bool) {
+        if !caught_up {
+            return;
+        }
+        let cutoff = Utc::now() - Duration::days(RELEASE_RETENTION_DAYS);
+        if self.published_after.is_some_and(|after| after >= cutoff) {
+            return;
+        }
+        self.published_after = Some(cutoff);
+        let before = self.package_timestamps.len();
+        self.package_timestamps.retain(|_, ts| *ts > cutoff);
+        println!(
+            "Forgot {} packages without a release since {cutoff}",
+            before - self.package_timestamps.len()
+        );
     }
 }
 
@@ -76,6 +286,27 @@ pub trait Source: Send + Display {
         Self: Sized;
     fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>>;
 
+    /// Resolves the files of a specific package, optionally limited to a single version. This does
+    /// not read or advance the cursor.
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>>;
+
+    /// Finds packages published between `since` and `until`. This does not read or advance the
+    /// cursor, so a window can be scanned again after an outage or a detector improvement.
+    fn get_packages_in_window(
+        &self,
+        _since: DateTime<Utc>,
+        _until: DateTime<Utc>,
+        _limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        Err(Unsupported("scanning a time window").into())
+    }
+
+    /// Advances a separate cursor that walks the history of the source from the beginning, up to
+    /// where the live cursor started. Both cursors are stored in the same state.
+    fn get_backfill_packages_to_process(&mut self, _limit: usize) -> Result<Vec<PackageToProcess>> {
+        Err(Unsupported("backfilling").into())
+    }
+
     fn to_state(&self) -> Result<SourceData>;
 
     fn get_stats(&mut self) -> &mut SourceStats;
@@ -84,10 +315,99 @@ pub trait Source: Send + Display {
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct SourceStats {
     packages_searched: u64,
+    #[serde(default)]
+    packages_unscanned: u64,
+    // Reason -> number of packages that were skipped for it, such as being too large.
+    #[serde(default)]
+    packages_skipped: BTreeMap<String, u64>,
+    // Account ID -> number of keys owned by it that were found but didn't work. The account is
+    // decoded from the key, so leaks can be grouped by account without verifying them.
+    #[serde(default)]
+    unverified_keys_by_account: BTreeMap<String, u64>,
 }
 
 impl SourceStats {
     pub fn add_packages_searched(&mut self, count: u64) {
         self.packages_searched += count;
     }
+
+    pub fn add_packages_unscanned(&mut self, count: u64) {
+        self.packages_unscanned += count;
+    }
+
+    pub fn add_package_skipped(&mut self, reason: &str) {
+        *self.packages_skipped.entry(reason.to_string()).or_default() += 1;
+    }
+
+    pub fn add_unverified_key(&mut self, account_id: &str) {
+        *self
+            .unverified_keys_by_account
+            .entry(account_id.to_string())
+            .or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_version_from_file_names() {
+        let cases = [
+            ("requests-2.28.1.tar.gz", "requests", "2.28.1"),
+            ("requests-2.28.1-py3-none-any.whl", "requests", "2.28.1"),
+            ("zope.interface-5.5.2-py3.8.egg", "zope.interface", "5.5.2"),
+            (
+                "my-internal-lib-0.1.0rc1.zip",
+                "my-internal-lib",
+                "0.1.0rc1",
+            ),
+            ("serde-1.0.152.crate", "serde", "1.0.152"),
+            ("left-pad-1.3.0.tgz", "left-pad", "1.3.0"),
+        ];
+        for (file_name, name, version) in cases {
+            assert_eq!(
+                parse_file_name(file_name),
+                Some((name.to_string(), version.to_string())),
+                "{file_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn release_cursor_only_returns_releases_after_the_last_one_queued() {
+        let start = Utc::now() - Duration::days(1);
+        let mut cursor = ReleaseCursor::starting_at(Some(start));
+        let later = start + Duration::hours(1);
+        assert!(!cursor.is_new("left-pad", start));
+        assert!(cursor.is_new("left-pad", later));
+        cursor.record("left-pad", later);
+        assert!(!cursor.is_new("left-pad", later));
+        assert!(cursor.is_new("left-pad", later + Duration::seconds(1)));
+        assert!(cursor.is_new("is-odd", later));
+    }
+
+    #[test]
+    fn release_cursor_only_forgets_packages_once_caught_up() {
+        let start = Utc::now() - Duration::days(100);
+        let mut cursor = ReleaseCursor::starting_at(Some(start));
+        cursor.record("old", start + Duration::days(1));
+        cursor.record("recent", Utc::now() - Duration::days(1));
+
+        cursor.forget_old_packages(false);
+        assert_eq!(cursor.published_after(), Some(start));
+        assert_eq!(cursor.tracked_packages(), 2);
+
+        cursor.forget_old_packages(true);
+        assert!(cursor.published_after().unwrap() > start + Duration::days(1));
+        assert_eq!(cursor.tracked_packages(), 1);
+        assert!(!cursor.is_new("old", start + Duration::days(2)));
+    }
+
+    #[test]
+    fn rejects_file_names_without_version_or_known_extension() {
+        assert_eq!(parse_file_name("requests.tar.gz"), None);
+        assert_eq!(parse_file_name("requests-latest.tar.gz"), None);
+        assert_eq!(parse_file_name("requests-2.28.1.exe"), None);
+    }
 }
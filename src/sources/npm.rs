@@ -0,0 +1,230 @@
+use crate::sources::{
+    get_json, PackageToProcess, ReleaseCursor, Source, SourceStats, SourceType, USER_AGENT,
+};
+use crate::state::SourceData;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_humanize::HumanTime;
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use url::Url;
+
+// The replication feed only lists the names of changed packages, so we need to fetch the
+// packument (the full package document) for each change to find the new tarballs.
+const CHANGES_URL: &str = "https://replicate.npmjs.com/registry/_changes";
+// Describes the replication database, including the seq of its latest change.
+const DATABASE_URL: &str = "https://replicate.npmjs.com/registry/";
+const REGISTRY_URL: &str = "https://registry.npmjs.org";
+
+#[derive(Serialize, Deserialize)]
+pub struct NpmSource {
+    // Not set until the first run, which starts from the latest change rather than reading the
+    // whole history of the registry.
+    last_seq: Option<u64>,
+    #[serde(flatten)]
+    releases: ReleaseCursor,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmDatabase {
+    update_seq: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmChangesResponse {
+    results: Vec<NpmChange>,
+    last_seq: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmChange {
+    seq: u64,
+    id: String,
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmPackument {
+    #[serde(default)]
+    versions: HashMap<String, NpmVersion>,
+    // Maps each version to its publish time. Also contains the `created` and `modified` keys, and
+    // an `unpublished` object for packages that have been removed.
+    #[serde(default)]
+    time: HashMap<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmVersion {
+    dist: NpmDist,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NpmDist {
+    tarball: Url,
+}
+
+impl Display for NpmSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let Some(last_seq) = self.last_seq else {
+            return write!(f, "npm - Starting from the latest change");
+        };
+        write!(
+            f,
+            "npm - Packages changed since seq {last_seq}, tracking {} packages",
+            self.releases.tracked_packages()
+        )?;
+        if let Some(published_after) = self.releases.published_after() {
+            write!(
+                f,
+                " published since {published_after} ({})",
+                HumanTime::from(published_after - Utc::now())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Source for NpmSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                last_seq: None,
+                releases: ReleaseCursor::starting_at(Some(Utc::now())),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let Some(last_seq) = self.last_seq else {
+            let database: NpmDatabase = get_json(&client, DATABASE_URL)?;
+            println!("Starting npm from seq {}", database.update_seq);
+            self.last_seq = Some(database.update_seq);
+            return Ok(vec![]);
+        };
+        let url = format!("{CHANGES_URL}?since={last_seq}&limit={limit}");
+        let response: NpmChangesResponse = get_json(&client, &url)?;
+        // The feed returns fewer changes than asked for once it reaches the latest one.
+        let reached_end = response.results.len() < limit;
+
+        let changes: Vec<_> = response
+            .results
+            .into_iter()
+            .filter(|c| !c.deleted && !c.id.starts_with("_design/"))
+            .collect();
+
+        println!("Fetching npm packuments for {} packages", changes.len());
+        let packuments: Result<Vec<_>> = changes
+            .into_par_iter()
+            .map(|change| {
+                let packument = fetch_packument(&client, &change.id)
+                    .with_context(|| format!("Error fetching npm package {}", change.id))?;
+                Ok((change, packument))
+            })
+            .collect();
+
+        // A change is emitted whenever a package document is modified, which includes things like
+        // dist-tag changes, so only versions published after the last one we queued for the
+        // package are new. Changes are processed in order, and the seq only moves past a change
+        // once all of its versions have been queued, so `limit` can fall within a change.
+        let mut to_process = vec![];
+        let mut complete = true;
+        for (change, packument) in packuments? {
+            let Some(mut packument) = packument else {
+                self.last_seq = Some(change.seq);
+                continue;
+            };
+            let versions = packument
+                .time
+                .into_iter()
+                .filter_map(|(version, published)| {
+                    let published = published.as_str()?.parse::<DateTime<Utc>>().ok()?;
+                    let details = packument.versions.remove(&version)?;
+                    Some((published, version, details.dist.tarball))
+                })
+                .filter(|(published, ..)| self.releases.is_new(&change.id, *published))
+                .sorted_by(|a, b| a.0.cmp(&b.0));
+
+            for (published, version, tarball) in versions {
+                if to_process.len() >= limit {
+                    complete = false;
+                    break;
+                }
+                self.releases.record(&change.id, published);
+                to_process.push(PackageToProcess::new(
+                    change.id.clone(),
+                    version,
+                    tarball,
+                    SourceType::Npm,
+                ));
+            }
+            if !complete {
+                break;
+            }
+            self.last_seq = Some(change.seq);
+        }
+        // If every change was processed, skip past any that were filtered out above.
+        if complete {
+            self.last_seq = Some(response.last_seq);
+        }
+        self.releases.forget_old_packages(complete && reached_end);
+
+        Ok(to_process)
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let Some(packument) = fetch_packument(&client, name)? else {
+            return Ok(vec![]);
+        };
+        Ok(packument
+            .versions
+            .into_iter()
+            .filter(|(v, _)| version.is_none_or(|version| version == v))
+            .map(|(version, details)| {
+                PackageToProcess::new(
+                    name.to_string(),
+                    version,
+                    details.dist.tarball,
+                    SourceType::Npm,
+                )
+            })
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+fn fetch_packument(client: &reqwest::blocking::Client, name: &str) -> Result<Option<NpmPackument>> {
+    // Scoped packages need the slash escaping, i.e `@scope%2fname`.
+    let url = format!("{REGISTRY_URL}/{}", name.replace('/', "%2f"));
+    let response = client
+        .get(&url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .with_context(|| format!("Failed to request URL {url}"))?;
+    // Packages can be unpublished between the change being recorded and us fetching it.
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    let packument = response
+        .error_for_status()?
+        .json()
+        .with_context(|| format!("Failed to parse JSON from {url}"))?;
+    Ok(Some(packument))
+}
@@ -0,0 +1,198 @@
+use crate::sources::{get_json, PackageToProcess, Source, SourceStats, SourceType};
+use crate::state::SourceData;
+use anyhow::Result;
+use chrono::prelude::*;
+use chrono_humanize::HumanTime;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+const CATALOG_INDEX_URL: &str = "https://api.nuget.org/v3/catalog0/index.json";
+const FLAT_CONTAINER_URL: &str = "https://api.nuget.org/v3-flatcontainer";
+
+#[derive(Serialize, Deserialize)]
+pub struct NuGetSource {
+    last_commit_timestamp: DateTime<Utc>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogIndex {
+    items: Vec<CatalogPage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogPage {
+    #[serde(rename = "@id")]
+    url: String,
+    #[serde(rename = "commitTimeStamp")]
+    commit_timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogPageResponse {
+    items: Vec<CatalogLeaf>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogLeaf {
+    #[serde(rename = "@type")]
+    leaf_type: String,
+    #[serde(rename = "commitTimeStamp")]
+    commit_timestamp: DateTime<Utc>,
+    #[serde(rename = "nuget:id")]
+    id: String,
+    #[serde(rename = "nuget:version")]
+    version: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FlatContainerIndex {
+    versions: Vec<String>,
+}
+
+impl Display for NuGetSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "NuGet - Catalog commits after {} ({})",
+            self.last_commit_timestamp,
+            HumanTime::from(self.last_commit_timestamp - Utc::now())
+        )
+    }
+}
+
+impl Source for NuGetSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                last_commit_timestamp: "2023-01-01T00:00:00Z".parse().unwrap(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let mut leaves = fetch_leaves(&client, self.last_commit_timestamp, None, limit)?;
+
+        // A commit is written atomically, so we never truncate in the middle of one. Otherwise the
+        // remaining leaves of that commit would be skipped on the next run.
+        let cutoff = match leaves.get(limit.saturating_sub(1)) {
+            Some(last) => {
+                let last_commit = last.commit_timestamp;
+                leaves
+                    .iter()
+                    .position(|l| l.commit_timestamp > last_commit)
+                    .unwrap_or(leaves.len())
+            }
+            None => leaves.len(),
+        };
+        leaves.truncate(cutoff);
+
+        if let Some(last) = leaves.last() {
+            self.last_commit_timestamp = last.commit_timestamp;
+        }
+
+        Ok(leaves_to_packages(leaves))
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let mut leaves = fetch_leaves(&client, since, Some(until), limit)?;
+        leaves.truncate(limit);
+        Ok(leaves_to_packages(leaves))
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let id = name.to_lowercase();
+        let url = format!("{FLAT_CONTAINER_URL}/{id}/index.json");
+        let index: FlatContainerIndex = get_json(&client, &url)?;
+        let version = version.map(normalize_version);
+        Ok(index
+            .versions
+            .into_iter()
+            .filter(|v| version.as_ref().is_none_or(|version| version == v))
+            .map(|version| package_for_version(&id, &version))
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+// Reads catalog pages in commit order until there are `limit` package details leaves, or a page
+// reaches `before`. Whole pages are read at a time, so this may return more than `limit` leaves.
+fn fetch_leaves(
+    client: &reqwest::blocking::Client,
+    after: DateTime<Utc>,
+    before: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Result<Vec<CatalogLeaf>> {
+    let index: CatalogIndex = get_json(client, CATALOG_INDEX_URL)?;
+
+    // Each page's timestamp is that of the newest commit it contains, so any page newer than
+    // `after` has at least one leaf we haven't seen yet.
+    let pages = index
+        .items
+        .into_iter()
+        .filter(|p| p.commit_timestamp > after)
+        .sorted_by_key(|p| p.commit_timestamp);
+
+    let mut leaves = vec![];
+    for page in pages {
+        if leaves.len() >= limit {
+            break;
+        }
+        let page_response: CatalogPageResponse = get_json(client, &page.url)?;
+        leaves.extend(page_response.items.into_iter().filter(|l| {
+            l.commit_timestamp > after
+                && before.is_none_or(|before| l.commit_timestamp < before)
+                && l.leaf_type == "nuget:PackageDetails"
+        }));
+        // Later pages only contain newer commits.
+        if before.is_some_and(|before| page.commit_timestamp >= before) {
+            break;
+        }
+    }
+    leaves.sort_by_key(|l| l.commit_timestamp);
+    Ok(leaves)
+}
+
+fn leaves_to_packages(leaves: Vec<CatalogLeaf>) -> Vec<PackageToProcess> {
+    leaves
+        .into_iter()
+        .map(|l| (l.id.to_lowercase(), normalize_version(&l.version)))
+        .unique()
+        .map(|(id, version)| package_for_version(&id, &version))
+        .collect()
+}
+
+fn package_for_version(id: &str, version: &str) -> PackageToProcess {
+    PackageToProcess::new(
+        id.to_string(),
+        version.to_string(),
+        format!("{FLAT_CONTAINER_URL}/{id}/{version}/{id}.{version}.nupkg")
+            .parse()
+            .unwrap(),
+        SourceType::NuGet,
+    )
+}
+
+// The flat container uses lowercased versions without any build metadata.
+fn normalize_version(version: &str) -> String {
+    version.split('+').next().unwrap_or(version).to_lowercase()
+}
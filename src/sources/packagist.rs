@@ -0,0 +1,328 @@
+use crate::sources::{get_json, PackageToProcess, Source, SourceStats, SourceType, USER_AGENT};
+use crate::state::SourceData;
+use anyhow::{bail, Context, Result};
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_humanize::HumanTime;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter};
+use url::Url;
+
+const CHANGES_URL: &str = "https://packagist.org/metadata/changes.json";
+const METADATA_URL: &str = "https://repo.packagist.org/p2";
+
+// The `time` of a version is the date of the tagged commit rather than when Packagist saw it, so a
+// tag pushed some time after it was created would be older than our cursor. We look back a bit
+// further than the cursor to catch these, at the cost of occasionally scanning a release twice.
+const VERSION_LOOKBACK_HOURS: i64 = 24;
+
+#[derive(Serialize, Deserialize)]
+pub struct PackagistSource {
+    // Packagist timestamps are in units of 1/10000th of a second.
+    since: Option<u64>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangesResponse {
+    #[serde(default)]
+    actions: Vec<ChangeAction>,
+    timestamp: u64,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeAction {
+    #[serde(rename = "type")]
+    action_type: String,
+    package: String,
+    time: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MetadataResponse {
+    packages: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PackagistVersion {
+    version: String,
+    time: Option<DateTime<FixedOffset>>,
+    dist: Option<PackagistDist>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PackagistDist {
+    #[serde(rename = "type")]
+    dist_type: String,
+    url: Url,
+}
+
+impl PackagistSource {
+    fn since_datetime(&self) -> Option<DateTime<Utc>> {
+        self.since
+            .and_then(|since| Utc.timestamp_opt((since / 10000) as i64, 0).single())
+    }
+}
+
+impl Display for PackagistSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.since_datetime() {
+            Some(ts) => write!(
+                f,
+                "Packagist - Packages changed since {} ({})",
+                ts,
+                HumanTime::from(ts - Utc::now())
+            ),
+            None => write!(f, "Packagist - No changes timestamp yet"),
+        }
+    }
+}
+
+impl Source for PackagistSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                since: None,
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let url = match self.since {
+            Some(since) => format!("{CHANGES_URL}?since={since}"),
+            None => CHANGES_URL.to_string(),
+        };
+        let response: ChangesResponse = get_json(&client, &url)?;
+
+        // Packagist only keeps a limited history of changes. If we have no timestamp, or ours is too
+        // old, then the response contains no actions and just a fresh timestamp to start from.
+        if let Some(error) = response.error {
+            if self.since.is_some() {
+                eprintln!("Packagist changes feed reset, some packages may be missed: {error}");
+            }
+            self.since = Some(response.timestamp);
+            return Ok(vec![]);
+        }
+
+        let not_before = self
+            .since_datetime()
+            .map(|ts| ts - Duration::hours(VERSION_LOOKBACK_HOURS));
+
+        let mut actions = updated_packages(response.actions, None);
+
+        // If there are more changes than our limit then resume from the first one we didn't take.
+        if actions.len() > limit {
+            self.since = Some(actions[limit].time as u64 * 10000);
+            actions.truncate(limit);
+        } else {
+            self.since = Some(response.timestamp);
+        }
+
+        fetch_actions(&client, actions, not_before, None)
+    }
+
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("{CHANGES_URL}?since={}", since.timestamp() * 10000);
+        let response: ChangesResponse = get_json(&client, &url)?;
+        if let Some(error) = response.error {
+            bail!("Packagist no longer has the changes since {since}: {error}");
+        }
+        let mut actions = updated_packages(response.actions, Some(until));
+        actions.truncate(limit);
+        let not_before = since - Duration::hours(VERSION_LOOKBACK_HOURS);
+        fetch_actions(&client, actions, Some(not_before), Some(until))
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let packages = fetch_dist_urls(&client, name, None, None)?;
+        Ok(packages
+            .into_iter()
+            .filter(|p| version.is_none_or(|version| version == p.version))
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+// Returns the package updates that happened before `before`, oldest first.
+fn updated_packages(
+    actions: Vec<ChangeAction>,
+    before: Option<DateTime<Utc>>,
+) -> Vec<ChangeAction> {
+    let mut actions: Vec<_> = actions
+        .into_iter()
+        .filter(|a| a.action_type == "update" && !a.package.ends_with("~dev"))
+        .filter(|a| before.is_none_or(|before| a.time < before.timestamp()))
+        .collect();
+    actions.sort_by_key(|a| a.time);
+    actions
+}
+
+fn fetch_actions(
+    client: &reqwest::blocking::Client,
+    actions: Vec<ChangeAction>,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+) -> Result<Vec<PackageToProcess>> {
+    println!("Fetching packagist metadata for {} packages", actions.len());
+    let packages_to_process: Result<Vec<_>> = actions
+        .into_par_iter()
+        .map(|action| {
+            fetch_dist_urls(client, &action.package, not_before, not_after)
+                .with_context(|| format!("Error fetching Packagist package {}", action.package))
+        })
+        .collect();
+    Ok(packages_to_process?.into_iter().flatten().collect())
+}
+
+fn fetch_dist_urls(
+    client: &reqwest::blocking::Client,
+    name: &str,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+) -> Result<Vec<PackageToProcess>> {
+    let url = format!("{METADATA_URL}/{name}.json");
+    let response = client
+        .get(&url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .with_context(|| format!("Failed to request URL {url}"))?;
+    // Packages can be deleted between the change being recorded and us fetching it.
+    if response.status() == 404 {
+        return Ok(vec![]);
+    }
+    let mut metadata: MetadataResponse = response
+        .error_for_status()?
+        .json()
+        .with_context(|| format!("Failed to parse JSON from {url}"))?;
+
+    let versions = match metadata.packages.remove(name) {
+        Some(Value::Array(versions)) => expand_minified(versions)?,
+        Some(other) => bail!("Unknown packagist metadata for {name}: {other:?}"),
+        None => return Ok(vec![]),
+    };
+
+    Ok(versions
+        .into_iter()
+        .filter(|v| match (not_before, v.time) {
+            (Some(not_before), Some(time)) => time >= not_before,
+            _ => true,
+        })
+        .filter(|v| match (not_after, v.time) {
+            (Some(not_after), Some(time)) => time <= not_after,
+            _ => true,
+        })
+        .filter_map(|v| match v.dist {
+            Some(dist) if dist.dist_type == "zip" => {
+                Some(package_for_dist(name, v.version, &dist.url))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+// Dist URLs end in a git ref rather than a file name, so one is made up from the package and
+// version, such as `monolog-3.5.0.zip` for `monolog/monolog`.
+fn package_for_dist(name: &str, version: String, url: &Url) -> PackageToProcess {
+    let package = name.rsplit('/').next().unwrap_or(name);
+    let file_name = format!("{package}-{version}.zip");
+    PackageToProcess::new(
+        name.to_string(),
+        version,
+        codeload_url(url).unwrap_or_else(|| url.clone()),
+        SourceType::Packagist,
+    )
+    .with_file_name(file_name)
+}
+
+// Most dist URLs are GitHub API zipball URLs, which are rate limited to 60 requests an hour without
+// a token. They redirect to codeload.github.com, which isn't, so we download from there directly.
+// https://api.github.com/repos/{owner}/{repo}/zipball/{ref}
+// -> https://codeload.github.com/{owner}/{repo}/legacy.zip/{ref}
+fn codeload_url(url: &Url) -> Option<Url> {
+    if url.host_str() != Some("api.github.com") {
+        return None;
+    }
+    match url.path_segments()?.collect::<Vec<_>>().as_slice() {
+        ["repos", owner, repo, "zipball", reference] => {
+            format!("https://codeload.github.com/{owner}/{repo}/legacy.zip/{reference}")
+                .parse()
+                .ok()
+        }
+        _ => None,
+    }
+}
+
+// Composer 2 metadata is "minified": each version only contains the keys that differ from the
+// previous version, with the special value `__unset` marking keys that have been removed.
+fn expand_minified(versions: Vec<Value>) -> Result<Vec<PackagistVersion>> {
+    let mut current = Map::new();
+    let mut expanded = vec![];
+    for version in versions {
+        let Value::Object(fields) = version else {
+            bail!("Unknown packagist version: {version:?}");
+        };
+        for (key, value) in fields {
+            if value == "__unset" {
+                current.remove(&key);
+            } else {
+                current.insert(key, value);
+            }
+        }
+        expanded.push(serde_json::from_value(Value::Object(current.clone()))?);
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_zipballs_use_codeload() {
+        let url = "https://api.github.com/repos/laravel/framework/zipball/0123abcd"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            codeload_url(&url).unwrap().as_str(),
+            "https://codeload.github.com/laravel/framework/legacy.zip/0123abcd"
+        );
+    }
+
+    #[test]
+    fn other_dist_urls_are_unchanged() {
+        let url = "https://gitlab.com/api/v4/projects/1/repository/archive.zip?sha=abc"
+            .parse()
+            .unwrap();
+        assert_eq!(codeload_url(&url), None);
+    }
+
+    #[test]
+    fn dists_are_named_as_zip_files() {
+        let url = "https://api.github.com/repos/laravel/framework/zipball/0123abcd"
+            .parse()
+            .unwrap();
+        let package = package_for_dist("laravel/framework", "v10.0.0".to_string(), &url);
+        assert_eq!(package.file_name(), "framework-v10.0.0.zip");
+    }
+}
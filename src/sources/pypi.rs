@@ -1,4 +1,7 @@
-use crate::sources::{PackageToProcess, Source, SourceStats, SourceType};
+use crate::sources::simple_index::normalize_name;
+use crate::sources::{
+    parse_file_name, PackageToProcess, ReleaseCursor, Source, SourceStats, SourceType, USER_AGENT,
+};
 use crate::state::SourceData;
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, TimeZone, Utc};
@@ -6,21 +9,53 @@ use chrono_humanize::HumanTime;
 use itertools::Itertools;
 use rand::prelude::*;
 use rayon::prelude::*;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::iter::Iterator;
 use url::Url;
 use xmlrpc::{Request, Value as XmlValue, Value};
 
+const SIMPLE_URL: &str = "https://pypi.org/simple/";
+// How many project pages we fetch in parallel before checking whether we've reached our limit.
+const SIMPLE_CHUNK_SIZE: usize = 100;
+
 #[derive(Serialize, Deserialize)]
 pub struct PyPiSource {
     changelog_serial: u64,
     last_package_timestamp: Option<DateTime<Utc>>,
     #[serde(default)]
+    backend: PyPiBackend,
+    // The simple backend has no cursor for files, only for projects, and a changed project lists
+    // every file it has.
+    #[serde(default)]
+    simple_releases: Option<ReleaseCursor>,
+    #[serde(default)]
+    backfill: Option<PyPiBackfill>,
+    #[serde(default)]
     stats: SourceStats,
 }
 
+/// A second cursor that walks the changelog from the beginning, independently of the live cursor.
+/// It starts at serial 0 and stops at the live `changelog_serial` from when it was created, as
+/// everything after that is scanned by the live cursor.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PyPiBackfill {
+    next_serial: u64,
+    end_serial: u64,
+}
+
+/// How we discover new files. The XML-RPC API is deprecated, so `simple` uses the JSON simple index
+/// and the serials it exposes instead. Both backends share the same `changelog_serial` cursor.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PyPiBackend {
+    #[default]
+    XmlRpc,
+    Simple,
+}
+
 impl Display for PyPiSource {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -36,43 +71,23 @@ impl Display for PyPiSource {
                 HumanTime::from(ts - Utc::now())
             )?;
         }
+        if let Some(backfill) = &self.backfill {
+            write!(
+                f,
+                ". Backfill at serial {} of {}",
+                backfill.next_serial, backfill.end_serial
+            )?;
+        }
         Ok(())
     }
 }
 
-impl Source for PyPiSource {
-    fn new(data: SourceData) -> Result<Self> {
-        match data {
-            SourceData::Null => Ok(Self {
-                changelog_serial: 0,
-                last_package_timestamp: None,
-                stats: Default::default(),
-            }),
-            _ => Ok(serde_json::from_value(data)?),
-        }
-    }
-
-    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+impl PyPiSource {
+    fn packages_from_changelog(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
         let changelog_request =
             Request::new("changelog_since_serial").arg(self.changelog_serial as i32);
-        let res = changelog_request
-            .call_url("https://pypi.org/pypi")
-            .with_context(|| format!("Error getting changelog serial: {changelog_request:?}"))?;
-        let changelog_items: Vec<_> = match res {
-            Value::Array(items) => {
-                let only_xml_vecs = items.iter().filter_map(|item| match item {
-                    XmlValue::Array(v) => Some(v),
-                    _ => None,
-                });
-                only_xml_vecs
-                    .filter_map(|v| parse_changelog_item(v))
-                    .take(limit)
-                    .collect()
-            }
-            _ => {
-                bail!("Unknown changelog response: {:?}", res);
-            }
-        };
+        let (changelog_items, _) = fetch_changelog(changelog_request)?;
+        let changelog_items: Vec<_> = changelog_items.into_iter().take(limit).collect();
         let highest_serial = changelog_items
             .iter()
             .map(|v| v.serial)
@@ -89,32 +104,195 @@ impl Source for PyPiSource {
         self.changelog_serial = highest_serial;
         self.last_package_timestamp = Some(highest_datetime);
 
-        // Now we have a vec of individual releases. We need to fetch the download URLs, which requires
-        // us to make 1 request per _package version_ to fetch N _releases_.
-        // To do this we create a hashmap mapping (name, version) -> [files].
-        let changelogs_by_packages = changelog_items
-            .into_iter()
-            .map(|v| ((v.package_name.clone(), v.version.clone()), v))
-            .into_group_map();
+        packages_for_changelog(changelog_items)
+    }
+
+    // The simple index lists every project along with the serial of its last change, so we can
+    // find the projects changed since our serial without using the XML-RPC API.
+    fn packages_from_simple_index(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let (index_serial, index) = fetch_simple_json::<SimpleProjectList>(&client, SIMPLE_URL)?;
+        if index_serial.is_some_and(|serial| serial <= self.changelog_serial) {
+            println!("No PyPi changes since serial {}", self.changelog_serial);
+            return Ok(vec![]);
+        }
 
+        let changed_projects: Vec<_> = index
+            .projects
+            .into_iter()
+            .filter(|p| {
+                p.last_serial > self.changelog_serial
+                    && !SKIP_PACKAGES.contains(&p.name.to_lowercase().as_str())
+            })
+            .sorted_by_key(|p| p.last_serial)
+            .collect();
         println!(
-            "Fetching pypi package info for {} packages",
-            changelogs_by_packages.len()
+            "Fetching pypi simple index pages for {} changed projects",
+            changed_projects.len()
         );
-        let packages_to_process: Result<Vec<_>> = changelogs_by_packages
-            .into_par_iter()
-            .map(|((name, version), changelogs)| {
-                fetch_download_url_for_package(&name, &version, changelogs)
-                    .with_context(|| format!("Error fetching PyPi package {name} - {version}"))
-            })
+
+        // When switching from the XML-RPC backend, every file uploaded before its timestamp has
+        // already been seen, as the changelog is in upload order.
+        let last_package_timestamp = self.last_package_timestamp;
+        let releases = self
+            .simple_releases
+            .get_or_insert_with(|| ReleaseCursor::starting_at(last_package_timestamp));
+        let mut packages = vec![];
+        let mut processed_projects = 0;
+        for chunk in changed_projects.chunks(SIMPLE_CHUNK_SIZE) {
+            if packages.len() >= limit {
+                break;
+            }
+            let project_files: Result<Vec<_>> = chunk
+                .par_iter()
+                .map(|project| {
+                    let url = format!("{SIMPLE_URL}{}/", normalize_name(&project.name));
+                    let (_, detail) = fetch_simple_json::<SimpleProjectDetail>(&client, &url)
+                        .with_context(|| format!("Error fetching PyPi project {}", project.name))?;
+                    Ok((project, detail.files))
+                })
+                .collect();
+
+            for (project, files) in project_files? {
+                let name = normalize_name(&project.name);
+                let new_files: Vec<_> = files
+                    .into_iter()
+                    .filter(|f| {
+                        !f.filename.ends_with(".exe")
+                            && f.upload_time
+                                .is_none_or(|uploaded| releases.is_new(&name, uploaded))
+                    })
+                    .collect();
+                for file in new_files {
+                    let Ok(url) = Url::parse(&file.url) else {
+                        continue;
+                    };
+                    if let Some(uploaded) = file.upload_time {
+                        releases.record(&name, uploaded);
+                    }
+                    let version = parse_file_name(&file.filename)
+                        .map(|(_, version)| version)
+                        .unwrap_or_default();
+                    packages.push(
+                        PackageToProcess::new(project.name.clone(), version, url, SourceType::PyPi)
+                            .with_size(file.size),
+                    );
+                }
+                // The project may have changed since the index was fetched, but only the serial
+                // it was listed with is known to be covered by every project before it.
+                self.changelog_serial = self.changelog_serial.max(project.last_serial);
+            }
+            processed_projects += chunk.len();
+        }
+
+        releases.forget_old_packages(processed_projects == changed_projects.len());
+        Ok(packages)
+    }
+}
+
+impl Source for PyPiSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                changelog_serial: 0,
+                last_package_timestamp: None,
+                backend: Default::default(),
+                simple_releases: None,
+                backfill: None,
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let mut flattened_packages = match self.backend {
+            PyPiBackend::XmlRpc => self.packages_from_changelog(limit)?,
+            PyPiBackend::Simple => self.packages_from_simple_index(limit)?,
+        };
+        flattened_packages.shuffle(&mut thread_rng());
+        Ok(flattened_packages)
+    }
+
+    fn get_backfill_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let end_serial = self.changelog_serial;
+        let backfill = self.backfill.get_or_insert(PyPiBackfill {
+            next_serial: 0,
+            end_serial,
+        });
+        if backfill.next_serial >= backfill.end_serial {
+            println!("PyPi backfill finished at serial {}", backfill.end_serial);
+            return Ok(vec![]);
+        }
+
+        let changelog_request =
+            Request::new("changelog_since_serial").arg(backfill.next_serial as i32);
+        let (changelog_items, last_serial) = fetch_changelog(changelog_request)?;
+        let changelog_items: Vec<_> = changelog_items
+            .into_iter()
+            .filter(|item| item.serial <= backfill.end_serial)
+            .take(limit)
             .collect();
-        let mut flattened_packages: Vec<_> = packages_to_process
-            .context("Error handling flattened packages")?
+
+        // Entries that aren't file uploads are dropped when parsing, so a response may contain no
+        // items despite not having reached the end of the changelog.
+        backfill.next_serial = match changelog_items.iter().map(|v| v.serial).max() {
+            Some(serial) => serial,
+            None => last_serial.unwrap_or(backfill.end_serial),
+        }
+        .min(backfill.end_serial);
+
+        packages_for_changelog(changelog_items)
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("https://pypi.org/pypi/{name}/json");
+        let response: PyPiProjectResponse = client
+            .get(&url)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to request URL {url}"))?
+            .json()
+            .with_context(|| format!("Failed to read JSON for URL {url}"))?;
+        Ok(response
+            .releases
             .into_iter()
-            .flatten()
+            .filter(|(v, _)| version.is_none_or(|version| version == v))
+            .flat_map(|(version, files)| {
+                files.into_iter().filter_map(move |file| {
+                    Some(
+                        PackageToProcess::new(
+                            name.to_string(),
+                            version.clone(),
+                            Url::parse(&file.url).ok()?,
+                            SourceType::PyPi,
+                        )
+                        .with_size(file.size),
+                    )
+                })
+            })
+            .collect())
+    }
+
+    // The simple index has no way to query by time, so windows always use the XML-RPC changelog.
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let changelog_request = Request::new("changelog")
+            .arg(since.timestamp() as i32)
+            .arg(true);
+        let (changelog_items, _) = fetch_changelog(changelog_request)?;
+        let changelog_items: Vec<_> = changelog_items
+            .into_iter()
+            .filter(|item| item.ts < until)
+            .take(limit)
             .collect();
-        flattened_packages.shuffle(&mut thread_rng());
-        Ok(flattened_packages)
+        packages_for_changelog(changelog_items)
     }
 
     fn to_state(&self) -> Result<SourceData> {
@@ -142,7 +320,7 @@ fn parse_changelog_item(value: &[XmlValue]) -> Option<ChangelogItem> {
                 && !SKIP_PACKAGES.contains(&&**name)
                 && !action.contains(".exe") =>
         {
-            let file_name = action.split(' ').last().unwrap();
+            let file_name = action.split(' ').next_back().unwrap();
             Some(ChangelogItem {
                 package_name: name.clone(),
                 version: version.clone(),
@@ -155,15 +333,81 @@ fn parse_changelog_item(value: &[XmlValue]) -> Option<ChangelogItem> {
     }
 }
 
+// Returns the file uploads in the changelog, along with the serial of the last entry of any kind.
+fn fetch_changelog(request: Request) -> Result<(Vec<ChangelogItem>, Option<u64>)> {
+    let res = request
+        .call_url("https://pypi.org/pypi")
+        .with_context(|| format!("Error getting changelog: {request:?}"))?;
+    match res {
+        Value::Array(items) => {
+            let only_xml_vecs: Vec<_> = items
+                .iter()
+                .filter_map(|item| match item {
+                    XmlValue::Array(v) => Some(v),
+                    _ => None,
+                })
+                .collect();
+            let last_serial = only_xml_vecs
+                .iter()
+                .filter_map(|v| match v.last() {
+                    Some(XmlValue::Int(serial)) => Some(*serial as u64),
+                    _ => None,
+                })
+                .max();
+            let changelog_items = only_xml_vecs
+                .into_iter()
+                .filter_map(|v| parse_changelog_item(v))
+                .collect();
+            Ok((changelog_items, last_serial))
+        }
+        _ => {
+            bail!("Unknown changelog response: {:?}", res);
+        }
+    }
+}
+
+fn packages_for_changelog(changelog_items: Vec<ChangelogItem>) -> Result<Vec<PackageToProcess>> {
+    // Now we have a vec of individual releases. We need to fetch the download URLs, which requires
+    // us to make 1 request per _package version_ to fetch N _releases_.
+    // To do this we create a hashmap mapping (name, version) -> [files].
+    let changelogs_by_packages = changelog_items
+        .into_iter()
+        .map(|v| ((v.package_name.clone(), v.version.clone()), v))
+        .into_group_map();
+
+    println!(
+        "Fetching pypi package info for {} packages",
+        changelogs_by_packages.len()
+    );
+    let packages_to_process: Result<Vec<_>> = changelogs_by_packages
+        .into_par_iter()
+        .map(|((name, version), changelogs)| {
+            fetch_download_url_for_package(&name, &version, changelogs)
+                .with_context(|| format!("Error fetching PyPi package {name} - {version}"))
+        })
+        .collect();
+    Ok(packages_to_process
+        .context("Error handling flattened packages")?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
 #[derive(Deserialize)]
 pub struct PyPiResponse {
     urls: Vec<PackageUrl>,
 }
 
+#[derive(Deserialize)]
+pub struct PyPiProjectResponse {
+    releases: HashMap<String, Vec<PackageUrl>>,
+}
+
 #[derive(Deserialize)]
 pub struct PackageUrl {
     url: String,
     filename: String,
+    size: Option<u64>,
 }
 
 fn fetch_download_url_for_package(
@@ -175,7 +419,7 @@ fn fetch_download_url_for_package(
     let url = format!("https://pypi.org/pypi/{name}/{version}/json");
     let response = client
         .get(&url)
-        .header("User-Agent", "https://github.com/orf/aws-creds-scanner")
+        .header("User-Agent", USER_AGENT)
         .send()
         .with_context(|| format!("Failed to request URL {url}"))?;
     // Some versions are not valid URLs. For example, `weightless-core @ 0.5.2.3-seecr-%`
@@ -191,22 +435,73 @@ fn fetch_download_url_for_package(
         .text()
         .with_context(|| format!("Error fetching text for URL {url}"))?;
     let response: PyPiResponse = serde_json::from_str(&text).with_context(|| {
-        format!(
-            "Failed to read JSON for URL {url} - Status: {status}. Text: {text}"
-        )
+        format!("Failed to read JSON for URL {url} - Status: {status}. Text: {text}")
     })?;
 
     let matching_urls = response
         .urls
         .into_iter()
         .filter(|v| file_names.contains(&v.filename))
-        .filter_map(|v| Url::parse(&v.url).ok());
+        .filter_map(|v| Some((Url::parse(&v.url).ok()?, v.size)));
 
     Ok(matching_urls
-        .map(|url| PackageToProcess::new(name.clone(), version.clone(), url, SourceType::PyPi))
+        .map(|(url, size)| {
+            PackageToProcess::new(name.clone(), version.clone(), url, SourceType::PyPi)
+                .with_size(size)
+        })
         .collect())
 }
 
+#[derive(Deserialize)]
+pub struct SimpleProjectList {
+    projects: Vec<SimpleProject>,
+}
+
+#[derive(Deserialize)]
+pub struct SimpleProject {
+    name: String,
+    #[serde(rename = "_last-serial")]
+    last_serial: u64,
+}
+
+#[derive(Deserialize)]
+pub struct SimpleProjectDetail {
+    files: Vec<SimpleFile>,
+}
+
+#[derive(Deserialize)]
+pub struct SimpleFile {
+    filename: String,
+    url: String,
+    #[serde(rename = "upload-time")]
+    upload_time: Option<DateTime<Utc>>,
+    // Added to the simple API by PEP 700.
+    size: Option<u64>,
+}
+
+// Returns the `X-PyPI-Last-Serial` of the response along with the parsed body.
+fn fetch_simple_json<T: DeserializeOwned>(
+    client: &reqwest::blocking::Client,
+    url: &str,
+) -> Result<(Option<u64>, T)> {
+    let response = client
+        .get(url)
+        .header("Accept", "application/vnd.pypi.simple.v1+json")
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to request URL {url}"))?;
+    let serial = response
+        .headers()
+        .get("X-PyPI-Last-Serial")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let body = response
+        .json()
+        .with_context(|| format!("Failed to parse JSON from {url}"))?;
+    Ok((serial, body))
+}
+
 // Taken from https://pypi.org/stats/
 // curl https://pypi.org/stats/ | hq '{top: .table th | [{name: @text}]}' | jq -r '[.top[].name | ascii_downcase] | join("\n")'
 const SKIP_PACKAGES: &[&str] = &[
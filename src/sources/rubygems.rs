@@ -1,17 +1,28 @@
 use crate::sources::{PackageToProcess, Source, SourceStats, SourceType};
 use crate::state::SourceData;
+use anyhow::Context;
 use anyhow::Result;
-use anyhow::{anyhow, Context};
 use chrono::prelude::*;
 use chrono::Duration;
 use chrono_humanize::HumanTime;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter};
 use url::Url;
 
+// How many hours of versions we request at a time. Every page of a timeframe is fetched, as the
+// versions within it are not returned in order.
+const TIMEFRAME_HOURS: i64 = 24;
+
 #[derive(Serialize, Deserialize)]
 pub struct RubyGemsSource {
     last_package_timestamp: DateTime<Utc>,
+    // The gems created at exactly `last_package_timestamp` that we have already processed, so that
+    // none are skipped or processed twice when `limit` falls between gems sharing a timestamp.
+    // These are gem file names, as unlike name@version they are unique across platforms.
+    #[serde(default)]
+    seen_at_timestamp: BTreeSet<String>,
     #[serde(default)]
     stats: SourceStats,
 }
@@ -24,6 +35,22 @@ pub struct RubyGemsResponse {
     version_created_at: DateTime<Utc>,
 }
 
+impl RubyGemsResponse {
+    fn file_name(&self) -> String {
+        self.gem_uri
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RubyGemsVersion {
+    number: String,
+    platform: String,
+}
+
 impl Display for RubyGemsSource {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -35,11 +62,58 @@ impl Display for RubyGemsSource {
     }
 }
 
+impl RubyGemsSource {
+    fn is_new(&self, version: &RubyGemsResponse) -> bool {
+        version.version_created_at > self.last_package_timestamp
+            || (version.version_created_at == self.last_package_timestamp
+                && !self.seen_at_timestamp.contains(&version.file_name()))
+    }
+
+    // Returns the first `limit` new versions from `fetched` in creation order, and moves the cursor
+    // past them.
+    fn take_new_versions(
+        &mut self,
+        fetched: Vec<RubyGemsResponse>,
+        limit: usize,
+    ) -> Vec<RubyGemsResponse> {
+        // Both ends of a timeframe are inclusive, so gems on the boundary are returned twice.
+        let mut results: Vec<_> = fetched
+            .into_iter()
+            .filter(|v| self.is_new(v))
+            .unique_by(|v| v.file_name())
+            .sorted_by(|a, b| {
+                (a.version_created_at, &a.name, &a.version).cmp(&(
+                    b.version_created_at,
+                    &b.name,
+                    &b.version,
+                ))
+            })
+            .collect();
+        results.truncate(limit);
+
+        if let Some(last) = results.last() {
+            let last_date = last.version_created_at;
+            if last_date != self.last_package_timestamp {
+                self.last_package_timestamp = last_date;
+                self.seen_at_timestamp.clear();
+            }
+            self.seen_at_timestamp.extend(
+                results
+                    .iter()
+                    .filter(|v| v.version_created_at == last_date)
+                    .map(|v| v.file_name()),
+            );
+        }
+        results
+    }
+}
+
 impl Source for RubyGemsSource {
     fn new(data: SourceData) -> Result<Self> {
         match data {
             SourceData::Null => Ok(Self {
                 last_package_timestamp: "2019-01-18T21:24:29Z".parse().unwrap(),
+                seen_at_timestamp: Default::default(),
                 stats: Default::default(),
             }),
             _ => Ok(serde_json::from_value(data)?),
@@ -49,52 +123,81 @@ impl Source for RubyGemsSource {
     fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
         // https://rubygems.org/api/v1/timeframe_versions.json?from=2019-01-18T21:24:29Z&to=2019-01-18T21:24:31Z
         // https://rubygems.org/api/v1/timeframe_versions.json?from=2019-01-18T21:24:29&to=2019-01-20T21:24:29&page=0
-        let end_date = self.last_package_timestamp + Duration::days(5);
-        let mut results = vec![];
-        let base_url = format!(
-            "https://rubygems.org/api/v1/timeframe_versions.json?from={}&to={}",
-            self.last_package_timestamp
-                .to_rfc3339_opts(SecondsFormat::Secs, true,),
-            end_date.to_rfc3339_opts(SecondsFormat::Secs, true,)
-        );
-        for page in 0..50 {
-            if results.len() >= limit {
-                break;
-            }
-            let url = format!("{base_url}&page={page}");
-            let response = reqwest::blocking::get(&url)
-                .with_context(|| format!("Failed to request {url}"))?;
-            let ruby_response: Vec<RubyGemsResponse> = response
-                .json()
-                .with_context(|| format!("Failed to parse JSON from {url}"))?;
-            if ruby_response.is_empty() {
-                break;
-            } else {
-                results.extend(ruby_response)
-            }
+        let now = Utc::now();
+        let mut results: Vec<RubyGemsResponse> = vec![];
+        let mut from = self.last_package_timestamp;
+        while results.len() < limit && from < now {
+            let to = from + Duration::hours(TIMEFRAME_HOURS);
+            results.extend(
+                fetch_timeframe(from, to)?
+                    .into_iter()
+                    .filter(|v| self.is_new(v)),
+            );
+            from = to;
         }
 
-        let results: Vec<_> = results.into_iter().take(limit).collect();
+        let to_process = self
+            .take_new_versions(results, limit)
+            .into_iter()
+            .map(response_to_package)
+            .collect();
 
-        let last_date = results
-            .iter()
-            .map(|v| v.version_created_at)
-            .max_by_key(|v| *v)
-            .ok_or_else(|| anyhow!("No gem releases found"))?;
+        Ok(to_process)
+    }
 
-        self.last_package_timestamp = last_date;
+    fn get_packages_in_window(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PackageToProcess>> {
+        let mut results = vec![];
+        let mut from = since;
+        while from < until && results.len() < limit {
+            let to = (from + Duration::hours(TIMEFRAME_HOURS)).min(until);
+            // Both ends of a timeframe are inclusive, so skip versions that the next one returns.
+            results.extend(
+                fetch_timeframe(from, to)?
+                    .into_iter()
+                    .filter(|v| v.version_created_at < to),
+            );
+            from = to;
+        }
+        results.truncate(limit);
+        Ok(results.into_iter().map(response_to_package).collect())
+    }
 
-        let to_process = results
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        // https://rubygems.org/api/v1/versions/rails.json
+        let url = format!("https://rubygems.org/api/v1/versions/{name}.json");
+        let response =
+            reqwest::blocking::get(&url).with_context(|| format!("Failed to request {url}"))?;
+        let versions: Vec<RubyGemsVersion> = response
+            .error_for_status()?
+            .json()
+            .with_context(|| format!("Failed to parse JSON from {url}"))?;
+        Ok(versions
             .into_iter()
-            .map(|v| PackageToProcess {
-                download_url: v.gem_uri,
-                name: v.name,
-                version: v.version,
-                source: SourceType::RubyGems,
+            .filter(|v| version.is_none_or(|version| version == v.number))
+            .map(|v| {
+                // Platform specific gems have the platform appended to their file name.
+                let file_name = match v.platform.as_str() {
+                    "ruby" => format!("{name}-{}.gem", v.number),
+                    platform => format!("{name}-{}-{platform}.gem", v.number),
+                };
+                PackageToProcess {
+                    download_url: format!("https://rubygems.org/gems/{file_name}")
+                        .parse()
+                        .unwrap(),
+                    name: name.to_string(),
+                    version: v.number,
+                    source: SourceType::RubyGems,
+                    size: None,
+                    authenticated: false,
+                    file_name: None,
+                }
             })
-            .collect();
-
-        Ok(to_process)
+            .collect())
     }
 
     fn to_state(&self) -> Result<SourceData> {
@@ -105,3 +208,121 @@ impl Source for RubyGemsSource {
         &mut self.stats
     }
 }
+
+fn fetch_timeframe(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<RubyGemsResponse>> {
+    let mut results = vec![];
+    let base_url = format!(
+        "https://rubygems.org/api/v1/timeframe_versions.json?from={}&to={}",
+        from.to_rfc3339_opts(SecondsFormat::Secs, true),
+        to.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    for page in 0.. {
+        let url = format!("{base_url}&page={page}");
+        let response =
+            reqwest::blocking::get(&url).with_context(|| format!("Failed to request {url}"))?;
+        let ruby_response: Vec<RubyGemsResponse> = response
+            .json()
+            .with_context(|| format!("Failed to parse JSON from {url}"))?;
+        if ruby_response.is_empty() {
+            break;
+        } else {
+            results.extend(ruby_response)
+        }
+    }
+    Ok(results)
+}
+
+fn response_to_package(response: RubyGemsResponse) -> PackageToProcess {
+    PackageToProcess {
+        download_url: response.gem_uri,
+        name: response.name,
+        version: response.version,
+        source: SourceType::RubyGems,
+        size: None,
+        authenticated: false,
+        file_name: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &str, created_at: &str) -> RubyGemsResponse {
+        RubyGemsResponse {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            gem_uri: format!("https://rubygems.org/gems/{name}-1.0.0.gem")
+                .parse()
+                .unwrap(),
+            version_created_at: created_at.parse().unwrap(),
+        }
+    }
+
+    fn names(versions: &[RubyGemsResponse]) -> Vec<&str> {
+        versions.iter().map(|v| v.name.as_str()).collect()
+    }
+
+    fn source() -> RubyGemsSource {
+        let mut source = RubyGemsSource::new(SourceData::Null).unwrap();
+        source.last_package_timestamp = "2023-01-01T00:00:00Z".parse().unwrap();
+        source
+    }
+
+    fn fetched() -> Vec<RubyGemsResponse> {
+        vec![
+            version("d", "2023-01-01T00:00:02Z"),
+            version("b", "2023-01-01T00:00:01Z"),
+            version("a", "2023-01-01T00:00:01Z"),
+            version("c", "2023-01-01T00:00:01Z"),
+        ]
+    }
+
+    #[test]
+    fn limit_between_gems_with_the_same_timestamp() {
+        let mut source = source();
+
+        let first = source.take_new_versions(fetched(), 2);
+        assert_eq!(names(&first), ["a", "b"]);
+        assert_eq!(
+            source.last_package_timestamp,
+            "2023-01-01T00:00:01Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // The next timeframe starts at the cursor, so the same gems are fetched again.
+        let second = source.take_new_versions(fetched(), 2);
+        assert_eq!(names(&second), ["c", "d"]);
+        assert_eq!(
+            source.seen_at_timestamp,
+            BTreeSet::from(["d-1.0.0.gem".to_string()])
+        );
+
+        assert!(source.take_new_versions(fetched(), 2).is_empty());
+    }
+
+    #[test]
+    fn gems_on_a_timeframe_boundary_are_taken_once() {
+        let mut source = source();
+        let mut fetched = fetched();
+        fetched.push(version("b", "2023-01-01T00:00:01Z"));
+        fetched.push(version("d", "2023-01-01T00:00:02Z"));
+
+        let taken = source.take_new_versions(fetched, 10);
+        assert_eq!(names(&taken), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn gems_at_or_before_the_cursor_are_skipped() {
+        let mut source = source();
+        let fetched = vec![
+            version("old", "2022-12-31T23:59:59Z"),
+            version("new", "2023-01-01T00:00:00Z"),
+        ];
+
+        let taken = source.take_new_versions(fetched, 10);
+        assert_eq!(names(&taken), ["new"]);
+
+        let again = vec![version("new", "2023-01-01T00:00:00Z")];
+        assert!(source.take_new_versions(again, 10).is_empty());
+    }
+}
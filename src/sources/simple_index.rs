@@ -0,0 +1,298 @@
+use crate::sources::{
+    parse_file_name, PackageToProcess, Source, SourceStats, SourceType, USER_AGENT,
+};
+use crate::state::SourceData;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use regex::Regex;
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt::{Display, Formatter};
+use url::Url;
+
+const JSON_CONTENT_TYPE: &str = "application/vnd.pypi.simple.v1+json";
+// Prefer the PEP 691 JSON API, but fall back to the PEP 503 HTML pages.
+const ACCEPT_HEADER: &str = "application/vnd.pypi.simple.v1+json, text/html;q=0.1";
+
+lazy_static! {
+    static ref ANCHOR_REGEX: Regex =
+        Regex::new(r#"(?is)<a\s[^>]*?href\s*=\s*["']([^"']*)["'][^>]*>(.*?)</a>"#).unwrap();
+    static ref NORMALIZE_REGEX: Regex = Regex::new("[-_.]+").unwrap();
+}
+
+/// Scans any PEP 503 or PEP 691 "simple" package index, such as a private devpi or Artifactory
+/// instance. The index URL is stored in the state file, or taken from `SIMPLE_INDEX_URL` if it
+/// isn't set. Credentials are never stored in the state: set `SIMPLE_INDEX_TOKEN` for bearer
+/// authentication, or `SIMPLE_INDEX_USERNAME` and `SIMPLE_INDEX_PASSWORD` for basic authentication.
+/// Credentials are only sent to the origin of the index, as pages may link to files elsewhere.
+#[derive(Serialize, Deserialize)]
+pub struct SimpleIndexSource {
+    index_url: Option<Url>,
+    // Project name -> file names we have already processed.
+    #[serde(default)]
+    seen_files: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    stats: SourceStats,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JsonProjectList {
+    projects: Vec<JsonProject>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JsonProject {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JsonProjectDetail {
+    files: Vec<JsonProjectFile>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JsonProjectFile {
+    filename: String,
+    url: String,
+}
+
+struct IndexLink {
+    text: String,
+    url: Url,
+}
+
+impl SimpleIndexSource {
+    fn index_url(&self) -> Result<Url> {
+        let url = match &self.index_url {
+            Some(url) => url.clone(),
+            None => env::var("SIMPLE_INDEX_URL")
+                .context("No index_url in the state and SIMPLE_INDEX_URL is not set")?
+                .parse()
+                .context("SIMPLE_INDEX_URL is not a valid URL")?,
+        };
+        Ok(with_trailing_slash(url))
+    }
+}
+
+impl Display for SimpleIndexSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seen: usize = self.seen_files.values().map(|f| f.len()).sum();
+        match self.index_url() {
+            Ok(url) => write!(f, "SimpleIndex - {url} ({seen} files seen)"),
+            Err(_) => write!(f, "SimpleIndex - No index URL configured"),
+        }
+    }
+}
+
+impl Source for SimpleIndexSource {
+    fn new(data: SourceData) -> Result<Self> {
+        match data {
+            SourceData::Null => Ok(Self {
+                index_url: None,
+                seen_files: Default::default(),
+                stats: Default::default(),
+            }),
+            _ => Ok(serde_json::from_value(data)?),
+        }
+    }
+
+    fn get_new_packages_to_process(&mut self, limit: usize) -> Result<Vec<PackageToProcess>> {
+        let client = Client::new();
+        let index_url = self.index_url()?;
+
+        let projects = fetch_projects(&client, &index_url)
+            .with_context(|| format!("Error fetching project list from {index_url}"))?;
+        println!(
+            "Fetching simple index files for {} projects",
+            projects.len()
+        );
+
+        let project_files: Result<Vec<_>> = projects
+            .into_par_iter()
+            .map(|(name, url)| {
+                let files = fetch_project_files(&client, &index_url, &url)
+                    .with_context(|| format!("Error fetching simple index project {name}"))?;
+                Ok((name, files))
+            })
+            .collect();
+
+        let mut to_process = vec![];
+        for (name, files) in project_files? {
+            let seen = self.seen_files.entry(name.clone()).or_default();
+            for file in files {
+                if to_process.len() >= limit {
+                    break;
+                }
+                if !seen.insert(file.text.clone()) {
+                    continue;
+                }
+                let version = parse_file_name(&file.text)
+                    .map(|(_, version)| version)
+                    .unwrap_or_default();
+                let authenticated = file.url.origin() == index_url.origin();
+                to_process.push(
+                    PackageToProcess::new(name.clone(), version, file.url, SourceType::SimpleIndex)
+                        .with_authentication(authenticated),
+                );
+            }
+        }
+        Ok(to_process)
+    }
+
+    fn get_package(&self, name: &str, version: Option<&str>) -> Result<Vec<PackageToProcess>> {
+        let client = Client::new();
+        let name = normalize_name(name);
+        let index_url = self.index_url()?;
+        let project_url = index_url.join(&format!("{name}/"))?;
+        let files = fetch_project_files(&client, &index_url, &project_url)
+            .with_context(|| format!("Error fetching simple index project {name}"))?;
+        Ok(files
+            .into_iter()
+            .filter_map(|file| {
+                let file_version = parse_file_name(&file.text)
+                    .map(|(_, version)| version)
+                    .unwrap_or_default();
+                if version.is_some_and(|version| version != file_version) {
+                    return None;
+                }
+                let authenticated = file.url.origin() == index_url.origin();
+                Some(
+                    PackageToProcess::new(
+                        name.clone(),
+                        file_version,
+                        file.url,
+                        SourceType::SimpleIndex,
+                    )
+                    .with_authentication(authenticated),
+                )
+            })
+            .collect())
+    }
+
+    fn to_state(&self) -> Result<SourceData> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn get_stats(&mut self) -> &mut SourceStats {
+        &mut self.stats
+    }
+}
+
+/// Adds any credentials configured for the simple index to a request. Callers must check that the
+/// request is to the index's origin.
+pub fn authenticate(request: RequestBuilder) -> RequestBuilder {
+    if let Ok(token) = env::var("SIMPLE_INDEX_TOKEN") {
+        return request.bearer_auth(token);
+    }
+    match env::var("SIMPLE_INDEX_USERNAME") {
+        Ok(username) => request.basic_auth(username, env::var("SIMPLE_INDEX_PASSWORD").ok()),
+        Err(_) => request,
+    }
+}
+
+// Returns (normalized project name, project page URL).
+fn fetch_projects(client: &Client, index_url: &Url) -> Result<Vec<(String, Url)>> {
+    let (is_json, body) = fetch_page(client, index_url, index_url)?;
+    if is_json {
+        let list: JsonProjectList = serde_json::from_str(&body)?;
+        list.projects
+            .into_iter()
+            .map(|p| {
+                let name = normalize_name(&p.name);
+                let url = index_url.join(&format!("{name}/"))?;
+                Ok((name, url))
+            })
+            .collect()
+    } else {
+        Ok(parse_links(index_url, &body)
+            .into_iter()
+            .map(|link| {
+                (
+                    normalize_name(link.text.trim().trim_end_matches('/')),
+                    with_trailing_slash(link.url),
+                )
+            })
+            .collect())
+    }
+}
+
+fn fetch_project_files(
+    client: &Client,
+    index_url: &Url,
+    project_url: &Url,
+) -> Result<Vec<IndexLink>> {
+    let (is_json, body) = fetch_page(client, index_url, project_url)?;
+    if is_json {
+        let detail: JsonProjectDetail = serde_json::from_str(&body)?;
+        detail
+            .files
+            .into_iter()
+            .map(|f| {
+                let mut url = project_url.join(&f.url)?;
+                url.set_fragment(None);
+                Ok(IndexLink {
+                    text: f.filename,
+                    url,
+                })
+            })
+            .collect()
+    } else {
+        Ok(parse_links(project_url, &body))
+    }
+}
+
+// Returns whether the response is PEP 691 JSON, and the body.
+fn fetch_page(client: &Client, index_url: &Url, url: &Url) -> Result<(bool, String)> {
+    let mut request = client.get(url.clone());
+    if url.origin() == index_url.origin() {
+        request = authenticate(request);
+    }
+    let response = request
+        .header("Accept", ACCEPT_HEADER)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to request URL {url}"))?;
+    let is_json = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with(JSON_CONTENT_TYPE))
+        .unwrap_or(false);
+    let body = response
+        .text()
+        .with_context(|| format!("Error fetching text for URL {url}"))?;
+    Ok((is_json, body))
+}
+
+fn parse_links(base_url: &Url, html: &str) -> Vec<IndexLink> {
+    ANCHOR_REGEX
+        .captures_iter(html)
+        .filter_map(|c| {
+            let href = c[1].replace("&amp;", "&");
+            let mut url = base_url.join(&href).ok()?;
+            url.set_fragment(None);
+            Some(IndexLink {
+                text: c[2].trim().to_string(),
+                url,
+            })
+        })
+        .collect()
+}
+
+// https://peps.python.org/pep-0503/#normalized-names
+pub(crate) fn normalize_name(name: &str) -> String {
+    NORMALIZE_REGEX.replace_all(name, "-").to_lowercase()
+}
+
+// Relative links are resolved against the page URL, which only works as expected for directories.
+fn with_trailing_slash(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
@@ -1,5 +1,6 @@
-use crate::sources::SourceType;
+use crate::sources::{PackageToProcess, SourceType};
 use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -9,9 +10,33 @@ use std::path::PathBuf;
 
 pub type SourceData = Value;
 
+// A failed package is retried after 1, 2, 4 and 8 hours, and then given up on.
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BACKOFF_HOURS: i64 = 1;
+
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub struct State {
     sources: HashMap<SourceType, SourceData>,
+    // Packages that failed to download or scan, to be retried on a later run.
+    #[serde(default)]
+    retries: HashMap<SourceType, Vec<FailedPackage>>,
+    // Packages that failed too many times and will not be retried.
+    #[serde(default)]
+    unscanned: HashMap<SourceType, Vec<FailedPackage>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FailedPackage {
+    pub package: PackageToProcess,
+    pub attempts: u32,
+    pub last_error: String,
+    pub next_attempt: DateTime<Utc>,
+}
+
+impl FailedPackage {
+    pub fn is_exhausted(&self) -> bool {
+        self.attempts >= MAX_ATTEMPTS
+    }
 }
 
 impl State {
@@ -40,4 +65,106 @@ impl State {
             Some(v) => v.clone(),
         }
     }
+
+    /// Removes and returns the failed packages for a source that are due to be retried.
+    pub fn take_due_retries(&mut self, source: &SourceType) -> Vec<FailedPackage> {
+        let now = Utc::now();
+        let Some(retries) = self.retries.get_mut(source) else {
+            return vec![];
+        };
+        let (due, pending) = retries.drain(..).partition(|f| f.next_attempt <= now);
+        *retries = pending;
+        due
+    }
+
+    /// Records a package that failed to download or scan, after `previous_attempts` earlier
+    /// failures. Returns the entry, which is moved to the unscanned list once it is exhausted.
+    pub fn record_failure(
+        &mut self,
+        package: PackageToProcess,
+        previous_attempts: u32,
+        error: &anyhow::Error,
+    ) -> FailedPackage {
+        let attempts = previous_attempts + 1;
+        let backoff = Duration::hours(RETRY_BACKOFF_HOURS * 2_i64.pow(previous_attempts));
+        let failed = FailedPackage {
+            attempts,
+            last_error: format!("{error:#}"),
+            next_attempt: Utc::now() + backoff,
+            package,
+        };
+        let queue = if failed.is_exhausted() {
+            &mut self.unscanned
+        } else {
+            &mut self.retries
+        };
+        queue
+            .entry(failed.package.source.clone())
+            .or_default()
+            .push(failed.clone());
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package() -> PackageToProcess {
+        PackageToProcess::new(
+            "package".to_string(),
+            "1.0.0".to_string(),
+            "https://example.com/package-1.0.0.tar.gz".parse().unwrap(),
+            SourceType::PyPi,
+        )
+    }
+
+    #[test]
+    fn record_failure_backs_off_exponentially() {
+        let mut state = State::default();
+        let error = anyhow::anyhow!("download failed");
+        for (previous_attempts, hours) in [(0, 1), (1, 2), (2, 4), (3, 8)] {
+            let before = Utc::now();
+            let failed = state.record_failure(package(), previous_attempts, &error);
+            let after = Utc::now();
+            assert_eq!(failed.attempts, previous_attempts + 1);
+            assert_eq!(failed.last_error, "download failed");
+            assert!(!failed.is_exhausted());
+            assert!(failed.next_attempt >= before + Duration::hours(hours));
+            assert!(failed.next_attempt <= after + Duration::hours(hours));
+        }
+        assert_eq!(state.retries[&SourceType::PyPi].len(), 4);
+        assert!(state.unscanned.is_empty());
+    }
+
+    #[test]
+    fn record_failure_gives_up_after_max_attempts() {
+        let mut state = State::default();
+        let error = anyhow::anyhow!("download failed");
+        let failed = state.record_failure(package(), MAX_ATTEMPTS - 1, &error);
+        assert!(failed.is_exhausted());
+        assert_eq!(state.unscanned[&SourceType::PyPi].len(), 1);
+        assert!(state.retries.is_empty());
+        assert!(state.take_due_retries(&SourceType::PyPi).is_empty());
+    }
+
+    #[test]
+    fn take_due_retries_only_takes_due_packages() {
+        let mut state = State::default();
+        let error = anyhow::anyhow!("download failed");
+        state.record_failure(package(), 0, &error);
+        state.record_failure(package(), 0, &error);
+        assert!(state.take_due_retries(&SourceType::PyPi).is_empty());
+        assert!(state.take_due_retries(&SourceType::Npm).is_empty());
+
+        state.retries.get_mut(&SourceType::PyPi).unwrap()[0].next_attempt =
+            Utc::now() - Duration::minutes(1);
+        let due = state.take_due_retries(&SourceType::PyPi);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+
+        // Due packages are removed, so they are only retried once per run.
+        assert!(state.take_due_retries(&SourceType::PyPi).is_empty());
+        assert_eq!(state.retries[&SourceType::PyPi].len(), 1);
+    }
 }